@@ -0,0 +1,57 @@
+use bytes::Bytes;
+use lru::LruCache;
+use std::sync::Mutex;
+
+/// A size-capped LRU cache for file contents, keyed by tree node id.
+///
+/// The trees only keep metadata; payloads live here and are regenerated
+/// by the filesystem when they have been evicted.
+#[derive(Debug)]
+pub struct ContentCache {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    entries: LruCache<u64, Bytes>,
+    size: usize,
+    capacity: usize,
+}
+
+impl ContentCache {
+    /// Create a new cache holding at most `capacity` bytes.
+    pub fn new(capacity: usize) -> ContentCache {
+        ContentCache {
+            inner: Mutex::new(Inner {
+                entries: LruCache::unbounded(),
+                size: 0,
+                capacity,
+            }),
+        }
+    }
+
+    /// Get the payload of a node, if it is still cached.
+    pub fn get(&self, id: u64) -> Option<Bytes> {
+        let inner = &mut *self.inner.lock().unwrap();
+        inner.entries.get(&id).cloned()
+    }
+
+    /// Store the payload of a node, evicting the least recently used
+    /// entries until the cache fits its capacity again.
+    pub fn put(&self, id: u64, data: Bytes) {
+        let inner = &mut *self.inner.lock().unwrap();
+        if data.len() > inner.capacity {
+            return;
+        }
+        inner.size += data.len();
+        if let Some(old) = inner.entries.put(id, data) {
+            inner.size -= old.len();
+        }
+        while inner.size > inner.capacity {
+            match inner.entries.pop_lru() {
+                Some((_, old)) => inner.size -= old.len(),
+                None => break,
+            }
+        }
+    }
+}
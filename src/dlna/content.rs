@@ -0,0 +1,319 @@
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use lru::LruCache;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{FsError, FsResult, ReadDirMeta};
+
+use super::escape;
+use crate::stream::{self, Media};
+use crate::tree;
+use crate::{mime, PhantomFs};
+
+// how deep a Search descends below its container. Every level lists
+// directories from the backend, so keep it shallow.
+const SEARCH_DEPTH: usize = 3;
+const SEARCH_LIMIT: usize = 1000;
+
+// how many objects are remembered, renderers browse from the root again
+// for the ones dropped.
+const MAX_OBJECTS: usize = 20000;
+
+/// A UPnP error code and description.
+#[derive(Debug, Clone, Copy)]
+pub struct Fault(pub u16, pub &'static str);
+
+impl Fault {
+    pub const INVALID_ACTION: Fault = Fault(401, "Invalid Action");
+    pub const INVALID_ARGS: Fault = Fault(402, "Invalid Args");
+    pub const ACTION_FAILED: Fault = Fault(501, "Action Failed");
+    pub const NO_SUCH_OBJECT: Fault = Fault(701, "No such object");
+    pub const NO_SUCH_CONTAINER: Fault = Fault(710, "No such container");
+}
+
+type Args = Vec<(&'static str, String)>;
+
+// an entry of the tree, as a DIDL-Lite object.
+struct Object {
+    id: u64,
+    parent: u64,
+    path: DavPath,
+    name: String,
+    is_dir: bool,
+    size: u64,
+    content_type: String,
+    // the stream of a playlist, which renderers play instead.
+    media: Option<Media>,
+}
+
+/// The ContentDirectory service. Object ids are the ids of tree nodes,
+/// with the root as `0` like renderers expect.
+pub struct ContentDirectory {
+    fs: Arc<dyn PhantomFs>,
+    base: String,
+    // path and parent of the objects last handed out to renderers,
+    // besides the root.
+    objects: Mutex<LruCache<u64, (DavPath, u64)>>,
+}
+
+impl ContentDirectory {
+    pub fn new(fs: Arc<dyn PhantomFs>, base: String) -> ContentDirectory {
+        ContentDirectory {
+            fs,
+            base,
+            objects: Mutex::new(LruCache::new(MAX_OBJECTS)),
+        }
+    }
+
+    pub async fn browse(
+        &self,
+        id: &str,
+        flag: &str,
+        start: usize,
+        count: usize,
+    ) -> Result<Args, Fault> {
+        let id = parse_id(id).ok_or(Fault::NO_SUCH_OBJECT)?;
+        let objects = match flag {
+            "BrowseMetadata" => vec![self.object(id).await.map_err(|_| Fault::NO_SUCH_OBJECT)?],
+            "BrowseDirectChildren" => self.list(id).await.map_err(|e| match e {
+                FsError::NotFound => Fault::NO_SUCH_OBJECT,
+                _ => Fault::ACTION_FAILED,
+            })?,
+            _ => return Err(Fault::INVALID_ARGS),
+        };
+        Ok(self.result(objects, start, count))
+    }
+
+    /// Search below a container. Only `upnp:class derivedfrom` and
+    /// `dc:title contains` are understood, other criteria match anything.
+    pub async fn search(
+        &self,
+        id: &str,
+        criteria: &str,
+        start: usize,
+        count: usize,
+    ) -> Result<Args, Fault> {
+        let id = parse_id(id).ok_or(Fault::NO_SUCH_CONTAINER)?;
+        let class = quoted_after(criteria, "derivedfrom");
+        let title = quoted_after(criteria, "contains").map(|t| t.to_lowercase());
+
+        let mut found = Vec::new();
+        let mut level = vec![id];
+        for _ in 0..SEARCH_DEPTH {
+            let mut next = Vec::new();
+            for id in level {
+                let objects = match self.list(id).await {
+                    Ok(objects) => objects,
+                    Err(e) => {
+                        tracing::warn!("search failed to list {}: {:?}", id, e);
+                        continue;
+                    }
+                };
+                for o in objects {
+                    if o.is_dir {
+                        next.push(o.id);
+                    }
+                    let matches = class.as_ref().is_none_or(|c| o.class().starts_with(c))
+                        && title
+                            .as_ref()
+                            .is_none_or(|t| o.title().to_lowercase().contains(t));
+                    if matches && found.len() < SEARCH_LIMIT {
+                        found.push(o);
+                    }
+                }
+            }
+            level = next;
+        }
+        Ok(self.result(found, start, count))
+    }
+
+    fn result(&self, objects: Vec<Object>, start: usize, count: usize) -> Args {
+        let total = objects.len();
+        let count = if count == 0 { total } else { count };
+        let page = objects.iter().skip(start).take(count).collect::<Vec<_>>();
+        let mut didl = String::from(concat!(
+            "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" ",
+            "xmlns:dc=\"http://purl.org/dc/elements/1.1/\" ",
+            "xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">"
+        ));
+        for o in &page {
+            didl += &self.didl(o);
+        }
+        didl += "</DIDL-Lite>";
+        vec![
+            ("Result", didl),
+            ("NumberReturned", page.len().to_string()),
+            ("TotalMatches", total.to_string()),
+            ("UpdateID", "1".to_owned()),
+        ]
+    }
+
+    fn didl(&self, o: &Object) -> String {
+        let (id, parent) = if o.id == tree::ROOT_ID {
+            ("0".to_owned(), "-1".to_owned())
+        } else {
+            (object_id(o.id), object_id(o.parent))
+        };
+        if o.is_dir {
+            return format!(
+                concat!(
+                    "<container id=\"{}\" parentID=\"{}\" restricted=\"1\" searchable=\"1\">",
+                    "<dc:title>{}</dc:title><upnp:class>{}</upnp:class></container>"
+                ),
+                id,
+                parent,
+                escape(&o.title()),
+                o.class()
+            );
+        }
+        // a playlist is played from its stream, whose size we don't know.
+        let res = match &o.media {
+            Some(media) => format!(
+                "<res protocolInfo=\"http-get:*:{}:*\">{}</res>",
+                media.content_type,
+                escape(&stream::url(&self.base, &media.id))
+            ),
+            None => format!(
+                "<res protocolInfo=\"http-get:*:{}:*\" size=\"{}\">{}{}</res>",
+                o.content_type,
+                o.size,
+                self.base,
+                escape(&o.path.as_url_string())
+            ),
+        };
+        format!(
+            concat!(
+                "<item id=\"{}\" parentID=\"{}\" restricted=\"1\">",
+                "<dc:title>{}</dc:title><upnp:class>{}</upnp:class>{}</item>"
+            ),
+            id,
+            parent,
+            escape(&o.title()),
+            o.class(),
+            res
+        )
+    }
+
+    async fn object(&self, id: u64) -> FsResult<Object> {
+        let (path, parent) = self.path(id)?;
+        let name = String::from_utf8_lossy(path.as_bytes())
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .filter(|n| !n.is_empty())
+            .unwrap_or("phantom")
+            .to_owned();
+        self.stat(id, parent, path, name).await
+    }
+
+    // the children of a container, remembering them for later requests.
+    async fn list(&self, id: u64) -> FsResult<Vec<Object>> {
+        let (path, _) = self.path(id)?;
+        let mut stream = self.fs.read_dir(&path, ReadDirMeta::None).await?;
+        let mut objects = Vec::new();
+        while let Some(entry) = stream.next().await {
+            let name = String::from_utf8_lossy(&entry.name()).into_owned();
+            let child = format!(
+                "{}/{}",
+                path.as_url_string().trim_end_matches('/'),
+                utf8_percent_encode(&name, NON_ALPHANUMERIC)
+            );
+            let child = DavPath::new(&child).map_err(|_| FsError::GeneralFailure)?;
+            let child_id = self.fs.node_id(&child).await?;
+            self.objects
+                .lock()
+                .unwrap()
+                .put(child_id, (child.clone(), id));
+            objects.push(self.stat(child_id, id, child, name).await?);
+        }
+        Ok(objects)
+    }
+
+    async fn stat(&self, id: u64, parent: u64, path: DavPath, name: String) -> FsResult<Object> {
+        let meta = self.fs.metadata(&path).await?;
+        let media = match meta.is_dir() {
+            true => None,
+            false => self.fs.media(&path).await.unwrap_or(None),
+        };
+        let content_type = match (&media, meta.is_dir()) {
+            (Some(media), _) => media.content_type.clone(),
+            (None, true) => String::new(),
+            (None, false) => match self.fs.content_type(&path).await {
+                Ok(t) => t,
+                Err(_) => mime::content_type(&name, None)
+                    .unwrap_or(mime::DEFAULT)
+                    .to_owned(),
+            },
+        };
+        Ok(Object {
+            id,
+            parent,
+            path,
+            name,
+            is_dir: meta.is_dir(),
+            size: meta.len(),
+            content_type,
+            media,
+        })
+    }
+
+    fn path(&self, id: u64) -> FsResult<(DavPath, u64)> {
+        if id == tree::ROOT_ID {
+            return Ok((DavPath::new("/").unwrap(), tree::ROOT_ID));
+        }
+        let mut objects = self.objects.lock().unwrap();
+        objects.get(&id).cloned().ok_or(FsError::NotFound)
+    }
+}
+
+impl Object {
+    fn class(&self) -> &'static str {
+        let t = self.content_type.as_str();
+        if self.is_dir {
+            "object.container.storageFolder"
+        } else if t.starts_with("video/") || t == "application/vnd.apple.mpegurl" {
+            "object.item.videoItem"
+        } else if t.starts_with("audio/") {
+            "object.item.audioItem.musicTrack"
+        } else if t.starts_with("image/") {
+            "object.item.imageItem.photo"
+        } else if t.starts_with("text/") {
+            "object.item.textItem"
+        } else {
+            "object.item"
+        }
+    }
+
+    // media is listed without its extension, which TVs show as is.
+    fn title(&self) -> String {
+        match self.name.rfind('.') {
+            Some(i) if i > 0 && !self.is_dir && self.class() != "object.item" => {
+                self.name[..i].to_owned()
+            }
+            _ => self.name.clone(),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Option<u64> {
+    match id {
+        "0" => Some(tree::ROOT_ID),
+        _ => id.parse().ok(),
+    }
+}
+
+fn object_id(id: u64) -> String {
+    match id {
+        tree::ROOT_ID => "0".to_owned(),
+        _ => id.to_string(),
+    }
+}
+
+// the first quoted string after `key` in a search criteria.
+fn quoted_after(criteria: &str, key: &str) -> Option<String> {
+    let rest = &criteria[criteria.find(key)? + key.len()..];
+    let start = rest.find('"')? + 1;
+    let len = rest[start..].find('"')?;
+    Some(rest[start..start + len].to_owned())
+}
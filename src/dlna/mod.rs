@@ -0,0 +1,263 @@
+mod content;
+mod ssdp;
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+
+use http::header::{CONTENT_TYPE, SERVER};
+use http::{Request, Response, StatusCode};
+use webdav_handler::body::Body;
+use xmltree::Element;
+
+use crate::PhantomFs;
+use content::{ContentDirectory, Fault};
+
+pub use ssdp::local_ip;
+
+/// Requests under this path go to the media server instead of WebDAV.
+pub const PREFIX: &str = "/.dlna/";
+
+const DEVICE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+
+// the media types offered to renderers, in GetProtocolInfo.
+const PROTOCOLS: &[&str] = &[
+    "video/mp4",
+    "video/x-matroska",
+    "video/mp2t",
+    "video/x-msvideo",
+    "application/vnd.apple.mpegurl",
+    "audio/mpeg",
+    "audio/flac",
+    "audio/mp4",
+    "image/jpeg",
+    "image/png",
+];
+
+/// A UPnP MediaServer exposing the filesystem to DLNA renderers, like
+/// smart TVs. Media is streamed from the WebDAV side of the server.
+pub struct Dlna {
+    uuid: String,
+    name: String,
+    ip: Ipv4Addr,
+    base: String,
+    content: ContentDirectory,
+}
+
+impl Dlna {
+    /// `addr` is where renderers reach this server. The device uuid is
+    /// kept in `db`, so renderers recognize the server after a restart.
+    pub fn new(fs: Arc<dyn PhantomFs>, db: &sled::Db, addr: SocketAddrV4, name: &str) -> Dlna {
+        let uuid = match db.get("dlna-uuid") {
+            Ok(Some(uuid)) => String::from_utf8_lossy(&uuid).into_owned(),
+            _ => {
+                let uuid = uuid::Uuid::new_v4().to_hyphenated().to_string();
+                if let Err(e) = db.insert("dlna-uuid", uuid.as_bytes()) {
+                    tracing::warn!("failed to store the dlna uuid: {}", e);
+                }
+                uuid
+            }
+        };
+        let base = format!("http://{}", addr);
+        Dlna {
+            uuid,
+            name: name.to_owned(),
+            ip: *addr.ip(),
+            content: ContentDirectory::new(fs, base.clone()),
+            base,
+        }
+    }
+
+    /// Announce the server on the local network and answer searches
+    /// from renderers, in the background.
+    pub fn announce(&self) {
+        let (uuid, ip) = (self.uuid.clone(), self.ip);
+        let location = format!("{}{}description.xml", self.base, PREFIX);
+        tokio::spawn(async move {
+            if let Err(e) = ssdp::run(uuid, location, ip).await {
+                tracing::error!("ssdp failed: {}", e);
+            }
+        });
+    }
+
+    pub async fn handle(&self, req: Request<hyper::Body>) -> Response<Body> {
+        let path = req.uri().path()[PREFIX.len()..].to_owned();
+        match (req.method().as_str(), path.as_str()) {
+            ("GET", "description.xml") => xml(StatusCode::OK, self.description()),
+            ("GET", "ContentDirectory.xml") => xml(
+                StatusCode::OK,
+                include_str!("ContentDirectory.xml").to_owned(),
+            ),
+            ("GET", "ConnectionManager.xml") => xml(
+                StatusCode::OK,
+                include_str!("ConnectionManager.xml").to_owned(),
+            ),
+            ("POST", "control/ContentDirectory") | ("POST", "control/ConnectionManager") => {
+                self.control(req).await
+            }
+            // nothing is ever evented, but some renderers insist on
+            // subscribing.
+            ("SUBSCRIBE", _) | ("UNSUBSCRIBE", _) => Response::builder()
+                .header("SID", format!("uuid:{}", uuid::Uuid::new_v4()))
+                .header("TIMEOUT", "Second-1800")
+                .body(Body::empty())
+                .unwrap(),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap(),
+        }
+    }
+
+    fn description(&self) -> String {
+        format!(
+            include_str!("description.xml"),
+            name = escape(&self.name),
+            version = env!("CARGO_PKG_VERSION"),
+            uuid = self.uuid,
+            device = DEVICE,
+            content_directory = CONTENT_DIRECTORY,
+            connection_manager = CONNECTION_MANAGER,
+            prefix = PREFIX,
+        )
+    }
+
+    async fn control(&self, req: Request<hyper::Body>) -> Response<Body> {
+        let service = if req.uri().path().ends_with("ContentDirectory") {
+            CONTENT_DIRECTORY
+        } else {
+            CONNECTION_MANAGER
+        };
+        let body = match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => body,
+            Err(_) => return fault(Fault::INVALID_ARGS),
+        };
+        let action = match Element::parse(&body[..])
+            .ok()
+            .and_then(|e| e.get_child("Body").cloned())
+            .and_then(|b| b.children.into_iter().find_map(|c| c.as_element().cloned()))
+        {
+            Some(action) => action,
+            None => return fault(Fault::INVALID_ACTION),
+        };
+        let arg = |name: &str| {
+            action
+                .get_child(name)
+                .and_then(|e| e.get_text())
+                .map(|t| t.into_owned())
+                .unwrap_or_default()
+        };
+        let index = |name: &str| arg(name).parse::<usize>().unwrap_or(0);
+
+        let res = match (service, action.name.as_str()) {
+            (CONTENT_DIRECTORY, "Browse") => {
+                self.content
+                    .browse(
+                        &arg("ObjectID"),
+                        &arg("BrowseFlag"),
+                        index("StartingIndex"),
+                        index("RequestedCount"),
+                    )
+                    .await
+            }
+            (CONTENT_DIRECTORY, "Search") => {
+                self.content
+                    .search(
+                        &arg("ContainerID"),
+                        &arg("SearchCriteria"),
+                        index("StartingIndex"),
+                        index("RequestedCount"),
+                    )
+                    .await
+            }
+            (CONTENT_DIRECTORY, "GetSearchCapabilities") => {
+                Ok(vec![("SearchCaps", "dc:title,upnp:class".to_owned())])
+            }
+            (CONTENT_DIRECTORY, "GetSortCapabilities") => Ok(vec![("SortCaps", String::new())]),
+            (CONTENT_DIRECTORY, "GetSystemUpdateID") => Ok(vec![("Id", "1".to_owned())]),
+            (CONNECTION_MANAGER, "GetProtocolInfo") => {
+                let source = PROTOCOLS
+                    .iter()
+                    .map(|p| format!("http-get:*:{}:*", p))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                Ok(vec![("Source", source), ("Sink", String::new())])
+            }
+            (CONNECTION_MANAGER, "GetCurrentConnectionIDs") => {
+                Ok(vec![("ConnectionIDs", "0".to_owned())])
+            }
+            (CONNECTION_MANAGER, "GetCurrentConnectionInfo") => Ok(vec![
+                ("RcsID", "-1".to_owned()),
+                ("AVTransportID", "-1".to_owned()),
+                ("ProtocolInfo", String::new()),
+                ("PeerConnectionManager", String::new()),
+                ("PeerConnectionID", "-1".to_owned()),
+                ("Direction", "Output".to_owned()),
+                ("Status", "OK".to_owned()),
+            ]),
+            _ => Err(Fault::INVALID_ACTION),
+        };
+
+        match res {
+            Ok(args) => {
+                let args = args
+                    .iter()
+                    .map(|(name, value)| format!("<{0}>{1}</{0}>", name, escape(value)))
+                    .collect::<String>();
+                let body = format!(
+                    "<u:{0}Response xmlns:u=\"{1}\">{2}</u:{0}Response>",
+                    action.name, service, args
+                );
+                xml(StatusCode::OK, envelope(&body))
+            }
+            Err(f) => fault(f),
+        }
+    }
+}
+
+fn envelope(body: &str) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" ",
+            "s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">",
+            "<s:Body>{}</s:Body></s:Envelope>"
+        ),
+        body
+    )
+}
+
+fn fault(f: Fault) -> Response<Body> {
+    let body = format!(
+        concat!(
+            "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>",
+            "<detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">",
+            "<errorCode>{}</errorCode><errorDescription>{}</errorDescription>",
+            "</UPnPError></detail></s:Fault>"
+        ),
+        f.0, f.1
+    );
+    xml(StatusCode::INTERNAL_SERVER_ERROR, envelope(&body))
+}
+
+fn xml(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+        .header(SERVER, ssdp::SERVER)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Whether the request is for the media server.
+pub fn is_dlna<B>(req: &Request<B>) -> bool {
+    req.uri().path().starts_with(PREFIX)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
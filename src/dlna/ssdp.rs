@@ -0,0 +1,132 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use super::{CONNECTION_MANAGER, CONTENT_DIRECTORY, DEVICE};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const PORT: u16 = 1900;
+// how long renderers may remember the server without hearing from it.
+const MAX_AGE: u64 = 1800;
+
+pub const SERVER: &str = concat!("Linux UPnP/1.0 phantom/", env!("CARGO_PKG_VERSION"));
+
+/// The address other hosts reach this one on, the one of the interface
+/// multicast goes out on.
+pub fn local_ip() -> io::Result<Ipv4Addr> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((GROUP, PORT))?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(_) => Err(io::Error::other("no ipv4 address")),
+    }
+}
+
+/// Announce the device, and answer M-SEARCH requests, until an error.
+pub async fn run(uuid: String, location: String, ip: Ipv4Addr) -> io::Result<()> {
+    let socket = Arc::new(bind(ip)?);
+    let targets = targets(&uuid);
+    tracing::info!("Announcing dlna server {} on {}", uuid, ip);
+
+    let (notify, notify_targets, notify_location) =
+        (socket.clone(), targets.clone(), location.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(MAX_AGE / 2));
+        loop {
+            interval.tick().await;
+            for (nt, usn) in &notify_targets {
+                let msg = format!(
+                    concat!(
+                        "NOTIFY * HTTP/1.1\r\n",
+                        "HOST: {}:{}\r\n",
+                        "CACHE-CONTROL: max-age={}\r\n",
+                        "LOCATION: {}\r\n",
+                        "NT: {}\r\n",
+                        "NTS: ssdp:alive\r\n",
+                        "SERVER: {}\r\n",
+                        "USN: {}\r\n\r\n"
+                    ),
+                    GROUP, PORT, MAX_AGE, notify_location, nt, SERVER, usn
+                );
+                if let Err(e) = notify.send_to(msg.as_bytes(), (GROUP, PORT)).await {
+                    tracing::warn!("ssdp notify failed: {}", e);
+                }
+            }
+        }
+    });
+
+    let mut buf = [0; 2048];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        let st = match search_target(&buf[..len]) {
+            Some(st) => st,
+            None => continue,
+        };
+        for (nt, usn) in targets
+            .iter()
+            .filter(|(nt, _)| st == "ssdp:all" || st == *nt)
+        {
+            let msg = format!(
+                concat!(
+                    "HTTP/1.1 200 OK\r\n",
+                    "CACHE-CONTROL: max-age={}\r\n",
+                    "EXT:\r\n",
+                    "LOCATION: {}\r\n",
+                    "SERVER: {}\r\n",
+                    "ST: {}\r\n",
+                    "USN: {}\r\n\r\n"
+                ),
+                MAX_AGE, location, SERVER, nt, usn
+            );
+            if let Err(e) = socket.send_to(msg.as_bytes(), from).await {
+                tracing::warn!("ssdp reply to {} failed: {}", from, e);
+            }
+        }
+    }
+}
+
+// port 1900 is shared with other UPnP software on the host.
+fn bind(ip: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+    socket.join_multicast_v4(&GROUP, &ip)?;
+    socket.set_multicast_if_v4(&ip)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// notification type and unique service name of everything announced.
+fn targets(uuid: &str) -> Vec<(String, String)> {
+    let device = format!("uuid:{}", uuid);
+    let mut targets = vec![(device.clone(), device.clone())];
+    for nt in &[
+        "upnp:rootdevice",
+        DEVICE,
+        CONTENT_DIRECTORY,
+        CONNECTION_MANAGER,
+    ] {
+        targets.push((nt.to_string(), format!("{}::{}", device, nt)));
+    }
+    targets
+}
+
+// the ST header of an M-SEARCH request.
+fn search_target(msg: &[u8]) -> Option<String> {
+    let msg = std::str::from_utf8(msg).ok()?;
+    let mut lines = msg.lines();
+    if !lines.next()?.starts_with("M-SEARCH") {
+        return None;
+    }
+    lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        match name.trim().eq_ignore_ascii_case("ST") {
+            true => Some(value.trim().to_owned()),
+            false => None,
+        }
+    })
+}
@@ -0,0 +1,107 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+/// Collapses concurrent calls for the same key into one in-flight future,
+/// so that e.g. many clients opening the same directory at once only
+/// cause a single request to the backend.
+pub struct SingleFlight<K, T: Clone> {
+    calls: Mutex<Calls<K, T>>,
+}
+
+struct Calls<K, T: Clone> {
+    seq: u64,
+    pending: HashMap<K, (u64, Shared<BoxFuture<'static, T>>)>,
+}
+
+impl<K: Eq + Hash + Clone, T: Clone + Send + Sync + 'static> SingleFlight<K, T> {
+    pub fn new() -> SingleFlight<K, T> {
+        SingleFlight {
+            calls: Mutex::new(Calls {
+                seq: 0,
+                pending: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Run the future returned by `f` for `key`. If a call for the same
+    /// key is already in flight, `f` is not called and the result of
+    /// the pending call is returned instead.
+    pub async fn run<F, Fut>(&self, key: K, f: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let (seq, call) = {
+            let calls = &mut *self.calls.lock().unwrap();
+            match calls.pending.get(&key) {
+                Some(call) => call.clone(),
+                None => {
+                    calls.seq += 1;
+                    let call = (calls.seq, f().boxed().shared());
+                    calls.pending.insert(key.clone(), call.clone());
+                    call
+                }
+            }
+        };
+        let res = call.await;
+
+        // forget the call, unless a new one for the same key was started.
+        let calls = &mut *self.calls.lock().unwrap();
+        if let Some((s, _)) = calls.pending.get(&key) {
+            if *s == seq {
+                calls.pending.remove(&key);
+            }
+        }
+        res
+    }
+}
+
+impl<K, T: Clone> Debug for SingleFlight<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let calls = self.calls.lock().unwrap();
+        f.debug_struct("SingleFlight")
+            .field("pending", &calls.pending.len())
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: Clone + Send + Sync + 'static> Default for SingleFlight<K, T> {
+    fn default() -> Self {
+        SingleFlight::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn concurrent_calls_share_one() {
+        let flight = SingleFlight::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let call = || {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                7
+            }
+        };
+
+        let (a, b) = futures::join!(flight.run(1, call()), flight.run(1, call()));
+        assert_eq!((a, b), (7, 7));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // other keys, and later calls, run on their own.
+        let (a, b) = futures::join!(flight.run(1, call()), flight.run(2, call()));
+        assert_eq!((a, b), (7, 7));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
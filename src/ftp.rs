@@ -0,0 +1,400 @@
+use std::io::SeekFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{DavMetaData, FsError, FsResult, OpenOptions, ReadDirMeta};
+
+use crate::time::Utc;
+use crate::PhantomFs;
+
+// how long a client has to open the data connection.
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// the longest command line accepted, with its line ending.
+const MAX_LINE: usize = 4096;
+
+/// Serve `fs` read only over FTP on `addr`, in passive mode only. Like
+/// the WebDAV server, any login is accepted.
+pub async fn serve(fs: Arc<dyn PhantomFs>, addr: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Serving FTP on {}", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let fs = fs.clone();
+        tokio::spawn(async move {
+            if let Err(e) = Session::new(fs, peer.ip()).run(stream).await {
+                tracing::warn!("ftp session of {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+// the state of a control connection.
+struct Session {
+    fs: Arc<dyn PhantomFs>,
+    // the client, the only one allowed to open data connections.
+    peer: IpAddr,
+    // the working directory, like `/a/b`.
+    cwd: String,
+    // waiting for the data connection of the next transfer.
+    passive: Option<TcpListener>,
+    // offset of the next RETR, set with REST.
+    rest: u64,
+}
+
+impl Session {
+    fn new(fs: Arc<dyn PhantomFs>, peer: IpAddr) -> Session {
+        Session {
+            fs,
+            peer,
+            cwd: "/".to_owned(),
+            passive: None,
+            rest: 0,
+        }
+    }
+
+    async fn run(mut self, stream: TcpStream) -> io::Result<()> {
+        let local = stream.local_addr()?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        reply(&mut writer, "220 phantom ready").await?;
+        loop {
+            buf.clear();
+            let len = (&mut reader)
+                .take(MAX_LINE as u64)
+                .read_until(b'\n', &mut buf)
+                .await?;
+            if len == 0 {
+                break;
+            }
+            if len == MAX_LINE && buf.last() != Some(&b'\n') {
+                skip_line(&mut reader).await?;
+                reply(&mut writer, "500 line too long").await?;
+                continue;
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            let (cmd, arg) = match line.split_once(' ') {
+                Some((cmd, arg)) => (cmd.to_ascii_uppercase(), arg.trim()),
+                None => (line.trim().to_ascii_uppercase(), ""),
+            };
+            tracing::debug!("ftp {} {}", cmd, if cmd == "PASS" { "***" } else { arg });
+            let res = match cmd.as_str() {
+                "USER" => "331 any password will do".to_owned(),
+                "PASS" => "230 logged in".to_owned(),
+                "SYST" => "215 UNIX Type: L8".to_owned(),
+                "FEAT" => "211-Features:\r\n EPSV\r\n PASV\r\n SIZE\r\n MDTM\r\n REST STREAM\r\n UTF8\r\n211 End".to_owned(),
+                "OPTS" | "TYPE" | "MODE" | "STRU" | "NOOP" => "200 ok".to_owned(),
+                "PWD" | "XPWD" => format!("257 \"{}\"", self.cwd.replace('"', "\"\"")),
+                "CWD" | "XCWD" => self.cwd(arg).await,
+                "CDUP" | "XCUP" => self.cwd("..").await,
+                "PASV" => self.passive(local, false).await,
+                "EPSV" => self.passive(local, true).await,
+                "REST" => match arg.parse() {
+                    Ok(rest) => {
+                        self.rest = rest;
+                        format!("350 restarting at {}", rest)
+                    }
+                    Err(_) => "501 invalid offset".to_owned(),
+                },
+                "SIZE" => match self.metadata(arg).await {
+                    Ok(meta) if !meta.is_dir() => format!("213 {}", meta.len()),
+                    Ok(_) => "550 not a file".to_owned(),
+                    Err(e) => error(e),
+                },
+                "MDTM" => match self.metadata(arg).await {
+                    Ok(meta) => format!("213 {}", Utc::new(meta.modified().unwrap_or(UNIX_EPOCH)).compact()),
+                    Err(e) => error(e),
+                },
+                "LIST" | "NLST" | "RETR" => {
+                    let rest = std::mem::take(&mut self.rest);
+                    self.transfer(&mut writer, &cmd, arg, rest).await?
+                }
+                "STOR" | "STOU" | "APPE" | "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR"
+                | "RNTO" | "SITE" => "550 read only fs".to_owned(),
+                "QUIT" => {
+                    reply(&mut writer, "221 bye").await?;
+                    return Ok(());
+                }
+                _ => "502 not implemented".to_owned(),
+            };
+            reply(&mut writer, &res).await?;
+        }
+        Ok(())
+    }
+
+    async fn cwd(&mut self, arg: &str) -> String {
+        let path = join(&self.cwd, arg);
+        match self.metadata(&path).await {
+            Ok(meta) if meta.is_dir() => {
+                self.cwd = path;
+                "250 ok".to_owned()
+            }
+            Ok(_) => "550 not a directory".to_owned(),
+            Err(e) => error(e),
+        }
+    }
+
+    // listen for the data connection of the next transfer, on the address
+    // the client reached us on.
+    async fn passive(&mut self, local: SocketAddr, extended: bool) -> String {
+        let listener = match TcpListener::bind(SocketAddr::new(local.ip(), 0)).await {
+            Ok(listener) => listener,
+            Err(e) => return format!("425 {}", e),
+        };
+        let port = match listener.local_addr() {
+            Ok(addr) => addr.port(),
+            Err(e) => return format!("425 {}", e),
+        };
+        self.passive = Some(listener);
+        match local.ip() {
+            _ if extended => format!("229 Entering Extended Passive Mode (|||{}|)", port),
+            IpAddr::V4(ip) => {
+                let ip = ip.to_string().replace('.', ",");
+                format!(
+                    "227 Entering Passive Mode ({},{},{})",
+                    ip,
+                    port >> 8,
+                    port & 0xff
+                )
+            }
+            IpAddr::V6(_) => "425 use EPSV".to_owned(),
+        }
+    }
+
+    // send a listing or a file over the data connection.
+    async fn transfer(
+        &mut self,
+        writer: &mut OwnedWriteHalf,
+        cmd: &str,
+        arg: &str,
+        rest: u64,
+    ) -> io::Result<String> {
+        let listener = match self.passive.take() {
+            Some(listener) => listener,
+            None => return Ok("425 use PASV first".to_owned()),
+        };
+        // list options like `-la` are ignored.
+        let arg = if arg.starts_with('-') {
+            arg.split_once(' ').map(|(_, a)| a.trim()).unwrap_or("")
+        } else {
+            arg
+        };
+        let path = join(&self.cwd, arg);
+        let meta = match self.metadata(&path).await {
+            Ok(meta) => meta,
+            Err(e) => return Ok(error(e)),
+        };
+        if cmd == "RETR" && meta.is_dir() {
+            return Ok("550 not a file".to_owned());
+        }
+
+        reply(writer, "150 opening data connection").await?;
+        let mut data = match tokio::time::timeout(DATA_TIMEOUT, self.accept(&listener)).await {
+            Ok(Ok(data)) => data,
+            _ => return Ok("425 can't open data connection".to_owned()),
+        };
+        let res = match cmd {
+            "RETR" => self.retr(&mut data, &path, rest).await,
+            _ => self.list(&mut data, &path, &*meta, cmd == "NLST").await,
+        };
+        data.shutdown().await.ok();
+        Ok(match res {
+            Ok(()) => "226 transfer complete".to_owned(),
+            Err(Error::Fs(e)) => error(e),
+            Err(Error::Io(e)) => format!("426 {}", e),
+        })
+    }
+
+    // the data connection of the client, others trying to steal the
+    // transfer are dropped.
+    async fn accept(&self, listener: &TcpListener) -> io::Result<TcpStream> {
+        loop {
+            let (data, addr) = listener.accept().await?;
+            if addr.ip() == self.peer {
+                return Ok(data);
+            }
+            tracing::warn!(
+                "ftp data connection from {} rejected, expected {}",
+                addr,
+                self.peer
+            );
+        }
+    }
+
+    async fn list(
+        &self,
+        data: &mut TcpStream,
+        path: &str,
+        meta: &dyn DavMetaData,
+        names: bool,
+    ) -> Result<(), Error> {
+        let mut out = String::new();
+        if !meta.is_dir() {
+            let name = path.rsplit('/').next().unwrap_or("");
+            out += &line(name, meta, names);
+        } else {
+            let dir = dav_path(path)?;
+            let mut stream = self.fs.read_dir(&dir, ReadDirMeta::Data).await?;
+            while let Some(entry) = stream.next().await {
+                let meta = entry.metadata().await?;
+                let name = String::from_utf8_lossy(&entry.name()).into_owned();
+                out += &line(&name, &*meta, names);
+            }
+        }
+        data.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn retr(&self, data: &mut TcpStream, path: &str, rest: u64) -> Result<(), Error> {
+        let options = OpenOptions {
+            read: true,
+            ..Default::default()
+        };
+        let mut file = self.fs.open(&dav_path(path)?, options).await?;
+        if rest > 0 {
+            file.seek(SeekFrom::Start(rest)).await?;
+        }
+        loop {
+            let chunk = file.read_bytes(CHUNK_SIZE).await?;
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            data.write_all(&chunk).await?;
+        }
+    }
+
+    async fn metadata(&self, arg: &str) -> FsResult<Box<dyn DavMetaData>> {
+        let path = dav_path(&join(&self.cwd, arg))?;
+        self.fs.metadata(&path).await
+    }
+}
+
+// what can go wrong during a transfer.
+enum Error {
+    Fs(FsError),
+    Io(io::Error),
+}
+
+impl From<FsError> for Error {
+    fn from(e: FsError) -> Error {
+        Error::Fs(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// drop the rest of a line that is too long, without keeping it.
+async fn skip_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<()> {
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                reader.consume(i + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+async fn reply(writer: &mut OwnedWriteHalf, res: &str) -> io::Result<()> {
+    writer.write_all(format!("{}\r\n", res).as_bytes()).await
+}
+
+fn error(e: FsError) -> String {
+    match e {
+        FsError::NotFound => "550 no such file or directory".to_owned(),
+        FsError::Forbidden => "550 permission denied".to_owned(),
+        _ => "451 backend failure".to_owned(),
+    }
+}
+
+// the absolute path of `arg` relative to `cwd`, without `.` and `..`.
+fn join(cwd: &str, arg: &str) -> String {
+    let full = if arg.starts_with('/') {
+        arg.to_owned()
+    } else {
+        format!("{}/{}", cwd, arg)
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    for seg in full.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            seg => segments.push(seg),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+fn dav_path(path: &str) -> FsResult<DavPath> {
+    let encoded = path
+        .split('/')
+        .map(|s| utf8_percent_encode(s, NON_ALPHANUMERIC).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    DavPath::new(&encoded).map_err(|_| FsError::NotFound)
+}
+
+// a line of a LIST in the format of `ls -l`, or only the name for NLST.
+fn line(name: &str, meta: &dyn DavMetaData, names: bool) -> String {
+    if names {
+        return format!("{}\r\n", name);
+    }
+    let perm = if meta.is_dir() {
+        "dr-xr-xr-x"
+    } else {
+        "-r--r--r--"
+    };
+    let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+    format!(
+        "{} 1 ftp ftp {:>12} {} {}\r\n",
+        perm,
+        meta.len(),
+        ls_time(modified),
+        name
+    )
+}
+
+// like `Nov 06 08:49`, or `Nov 06  1994` for times older than half a year.
+fn ls_time(t: SystemTime) -> String {
+    let recent = SystemTime::now()
+        .duration_since(t)
+        .map(|d| d.as_secs() < 183 * 86400)
+        .unwrap_or(true);
+    let date = Utc::new(t);
+    if recent {
+        format!(
+            "{} {:02} {:02}:{:02}",
+            date.month_name(),
+            date.day,
+            date.hour,
+            date.min
+        )
+    } else {
+        format!("{} {:02}  {}", date.month_name(), date.day, date.year)
+    }
+}
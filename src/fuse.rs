@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{
+    Config, Errno, FileAttr, FileHandle, FileType, Filesystem, FopenFlags, Generation, INodeNo,
+    LockOwner, MountOption, OpenAccMode, OpenFlags, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, Request,
+};
+use futures::StreamExt;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio::runtime::Handle;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{
+    DavFile, DavFileSystem, DavMetaData, FsError, FsFuture, FsResult, OpenOptions, ReadDirMeta,
+};
+
+use crate::tree;
+
+// how long the kernel may cache attributes and lookups.
+const TTL: Duration = Duration::from_secs(1);
+
+const BLOCK_SIZE: u64 = 4096;
+
+/// Filesystems that can be mounted through FUSE. The ids of their tree
+/// nodes are used as inode numbers.
+pub trait Inodes: DavFileSystem {
+    /// Id of the tree node at `path`.
+    fn node_id<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, u64>;
+}
+
+/// Mount `fs` read only at `dir`, and serve it until it gets unmounted.
+pub async fn mount(fs: Arc<dyn Inodes>, dir: &str) -> io::Result<()> {
+    let mut paths = HashMap::new();
+    paths.insert(tree::ROOT_ID, (DavPath::new("/").unwrap(), 0));
+    let mount = Mount {
+        fs,
+        rt: Handle::current(),
+        paths: Arc::new(Mutex::new(paths)),
+        files: Arc::new(Mutex::new(HashMap::new())),
+        next_fh: AtomicU64::new(1),
+    };
+    let mut config = Config::default();
+    config.mount_options = vec![MountOption::RO, MountOption::FSName("phantom".to_owned())];
+    let dir = dir.to_owned();
+    tokio::task::spawn_blocking(move || fuser::mount(mount, dir, &config)).await?
+}
+
+// the FUSE side of a mount. Requests are handled on the tokio runtime,
+// so a slow backend doesn't hold up the kernel.
+struct Mount {
+    fs: Arc<dyn Inodes>,
+    rt: Handle,
+    // the path of every inode the kernel looked up, with the number of
+    // lookups it hasn't forgotten yet.
+    paths: Arc<Mutex<HashMap<u64, (DavPath, u64)>>>,
+    // the open files by handle, with their position.
+    files: Arc<Mutex<HashMap<u64, Arc<OpenFile>>>>,
+    next_fh: AtomicU64,
+}
+
+type OpenFile = tokio::sync::Mutex<(Box<dyn DavFile>, u64)>;
+
+impl Mount {
+    fn path(&self, ino: INodeNo) -> Result<DavPath, Errno> {
+        let paths = self.paths.lock().unwrap();
+        paths
+            .get(&ino.0)
+            .map(|(path, _)| path.clone())
+            .ok_or(Errno::ENOENT)
+    }
+
+    fn file(&self, fh: FileHandle) -> Result<Arc<OpenFile>, Errno> {
+        let files = self.files.lock().unwrap();
+        files.get(&fh.0).cloned().ok_or(Errno::EBADF)
+    }
+}
+
+impl Filesystem for Mount {
+    fn lookup(&self, req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        let path = match self.path(parent).and_then(|p| child(&p, name.as_bytes())) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        let (fs, paths, uid, gid) = (self.fs.clone(), self.paths.clone(), req.uid(), req.gid());
+        self.rt.spawn(async move {
+            match stat(&*fs, &path).await {
+                Ok((ino, meta)) => {
+                    let mut paths = paths.lock().unwrap();
+                    paths.entry(ino).or_insert((path, 0)).1 += 1;
+                    reply.entry(&TTL, &attr(ino, &*meta, uid, gid), Generation(0));
+                }
+                Err(e) => reply.error(errno(e)),
+            }
+        });
+    }
+
+    fn forget(&self, _req: &Request, ino: INodeNo, nlookup: u64) {
+        let mut paths = self.paths.lock().unwrap();
+        if let Some((_, lookups)) = paths.get_mut(&ino.0) {
+            *lookups = lookups.saturating_sub(nlookup);
+            if *lookups == 0 && ino.0 != tree::ROOT_ID {
+                paths.remove(&ino.0);
+            }
+        }
+    }
+
+    fn getattr(&self, req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        let path = match self.path(ino) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        let (fs, uid, gid) = (self.fs.clone(), req.uid(), req.gid());
+        self.rt.spawn(async move {
+            match fs.metadata(&path).await {
+                Ok(meta) => reply.attr(&TTL, &attr(ino.0, &*meta, uid, gid)),
+                Err(e) => reply.error(errno(e)),
+            }
+        });
+    }
+
+    fn open(&self, _req: &Request, ino: INodeNo, flags: OpenFlags, reply: ReplyOpen) {
+        if flags.acc_mode() != OpenAccMode::O_RDONLY {
+            return reply.error(Errno::EROFS);
+        }
+        let path = match self.path(ino) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        let (fs, files) = (self.fs.clone(), self.files.clone());
+        self.rt.spawn(async move {
+            let options = OpenOptions {
+                read: true,
+                ..Default::default()
+            };
+            match fs.open(&path, options).await {
+                Ok(file) => {
+                    let file = Arc::new(tokio::sync::Mutex::new((file, 0)));
+                    files.lock().unwrap().insert(fh, file);
+                    // some sizes are only known once a file was read, so
+                    // don't let the kernel cut reads short.
+                    reply.opened(FileHandle(fh), FopenFlags::FOPEN_DIRECT_IO);
+                }
+                Err(e) => reply.error(errno(e)),
+            }
+        });
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        let file = match self.file(fh) {
+            Ok(file) => file,
+            Err(e) => return reply.error(e),
+        };
+        self.rt.spawn(async move {
+            let mut file = file.lock().await;
+            let (file, pos) = &mut *file;
+            let res = async {
+                // sequential reads carry on where the last one stopped.
+                if *pos != offset {
+                    *pos = file.seek(SeekFrom::Start(offset)).await?;
+                }
+                let data = file.read_bytes(size as usize).await?;
+                *pos += data.len() as u64;
+                Ok(data)
+            };
+            match res.await {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    // the position is unknown after a failed read.
+                    *pos = u64::MAX;
+                    reply.error(errno(e))
+                }
+            }
+        });
+    }
+
+    fn release(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        fh: FileHandle,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.files.lock().unwrap().remove(&fh.0);
+        reply.ok();
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        let path = match self.path(ino) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        let fs = self.fs.clone();
+        self.rt.spawn(async move {
+            let entries = match list(&*fs, &path).await {
+                Ok(entries) => entries,
+                Err(e) => return reply.error(errno(e)),
+            };
+            let dots = vec![
+                (ino.0, FileType::Directory, b".".to_vec()),
+                (ino.0, FileType::Directory, b"..".to_vec()),
+            ];
+            let entries = dots.into_iter().chain(entries);
+            // offsets are the position of the next entry.
+            for (i, (ino, kind, name)) in entries.enumerate().skip(offset as usize) {
+                if reply.add(INodeNo(ino), i as u64 + 1, kind, OsStr::from_bytes(&name)) {
+                    break;
+                }
+            }
+            reply.ok();
+        });
+    }
+
+    fn statfs(&self, _req: &Request, _ino: INodeNo, reply: ReplyStatfs) {
+        let fs = self.fs.clone();
+        self.rt.spawn(async move {
+            let (used, total) = fs.get_quota().await.unwrap_or((0, None));
+            let total = total.unwrap_or(used).max(used) / BLOCK_SIZE;
+            let free = total - used / BLOCK_SIZE;
+            let bsize = BLOCK_SIZE as u32;
+            reply.statfs(total, free, free, 0, 0, bsize, 255, bsize);
+        });
+    }
+}
+
+async fn stat(fs: &dyn Inodes, path: &DavPath) -> FsResult<(u64, Box<dyn DavMetaData>)> {
+    let ino = fs.node_id(path).await?;
+    let meta = fs.metadata(path).await?;
+    Ok((ino, meta))
+}
+
+// the entries of a directory. The kernel looks each one up before
+// using its inode, which is when its path gets remembered.
+async fn list(fs: &dyn Inodes, path: &DavPath) -> FsResult<Vec<(u64, FileType, Vec<u8>)>> {
+    let mut stream = fs.read_dir(path, ReadDirMeta::None).await?;
+    let mut entries = Vec::new();
+    while let Some(entry) = stream.next().await {
+        let name = entry.name();
+        let path = child(path, &name).map_err(|_| FsError::GeneralFailure)?;
+        let meta = entry.metadata().await?;
+        let ino = fs.node_id(&path).await?;
+        let kind = if meta.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::RegularFile
+        };
+        entries.push((ino, kind, name));
+    }
+    Ok(entries)
+}
+
+fn child(parent: &DavPath, name: &[u8]) -> Result<DavPath, Errno> {
+    let name = std::str::from_utf8(name).map_err(|_| Errno::ENOENT)?;
+    let parent = parent.as_url_string();
+    let path = format!(
+        "{}/{}",
+        parent.trim_end_matches('/'),
+        utf8_percent_encode(name, NON_ALPHANUMERIC)
+    );
+    DavPath::new(&path).map_err(|_| Errno::ENOENT)
+}
+
+fn attr(ino: u64, meta: &dyn DavMetaData, uid: u32, gid: u32) -> FileAttr {
+    let mtime = meta.modified().unwrap_or(UNIX_EPOCH);
+    let (kind, perm, nlink) = if meta.is_dir() {
+        (FileType::Directory, 0o555, 2)
+    } else {
+        (FileType::RegularFile, 0o444, 1)
+    };
+    FileAttr {
+        ino: INodeNo(ino),
+        size: meta.len(),
+        blocks: meta.len().div_ceil(512),
+        atime: mtime,
+        mtime,
+        ctime: mtime,
+        crtime: meta.created().unwrap_or(mtime),
+        kind,
+        perm,
+        nlink,
+        uid,
+        gid,
+        rdev: 0,
+        blksize: BLOCK_SIZE as u32,
+        flags: 0,
+    }
+}
+
+fn errno(e: FsError) -> Errno {
+    match e {
+        FsError::NotFound => Errno::ENOENT,
+        FsError::Forbidden => Errno::EACCES,
+        FsError::Exists => Errno::EEXIST,
+        FsError::NotImplemented => Errno::ENOSYS,
+        _ => Errno::EIO,
+    }
+}
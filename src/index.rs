@@ -0,0 +1,199 @@
+use std::time::SystemTime;
+
+use futures::StreamExt;
+use http::header::{ACCEPT, CONTENT_TYPE};
+use http::{Method, Request, Response};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use webdav_handler::body::Body;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{FsError, FsResult, ReadDirMeta};
+
+use crate::mime;
+use crate::stream::{self, Media};
+use crate::time::Utc;
+use crate::PhantomFs;
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+    // the stream a playlist stub points at, played in its place.
+    media: Option<Media>,
+}
+
+/// Answer a browser's GET on a collection with an HTML index. Other
+/// requests, and WebDAV clients, get None and go to the handler.
+pub async fn serve<B>(fs: &dyn PhantomFs, req: &Request<B>) -> Option<Response<Body>> {
+    let html = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .map(|a| a.contains("text/html"))
+        .unwrap_or(false);
+    if req.method() != Method::GET || !html {
+        return None;
+    }
+    let path = DavPath::from_uri(req.uri()).ok()?;
+    if !fs.metadata(&path).await.ok()?.is_dir() {
+        return None;
+    }
+    let page = match render(fs, &path).await {
+        Ok(page) => page,
+        Err(e) => {
+            tracing::error!("index of {} failed: {:?}", path, e);
+            return None;
+        }
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(page))
+        .ok()
+}
+
+async fn render(fs: &dyn PhantomFs, path: &DavPath) -> FsResult<String> {
+    let mut entries = Vec::new();
+    let mut stream = fs.read_dir(path, ReadDirMeta::Data).await?;
+    while let Some(entry) = stream.next().await {
+        let meta = entry.metadata().await?;
+        let name = String::from_utf8_lossy(&entry.name()).into_owned();
+        let media = if meta.is_dir() {
+            None
+        } else {
+            let child = format!(
+                "{}/{}",
+                path.as_url_string().trim_end_matches('/'),
+                utf8_percent_encode(&name, NON_ALPHANUMERIC)
+            );
+            let child = DavPath::new(&child).map_err(|_| FsError::GeneralFailure)?;
+            fs.media(&child).await.unwrap_or(None)
+        };
+        entries.push(Entry {
+            name,
+            is_dir: meta.is_dir(),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            media,
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let segments = String::from_utf8_lossy(path.as_bytes())
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect::<Vec<_>>();
+    let href = |segments: &[String]| {
+        let mut href = String::from("/");
+        for s in segments {
+            href += &utf8_percent_encode(s, NON_ALPHANUMERIC).to_string();
+            href += "/";
+        }
+        href
+    };
+
+    let mut crumbs = String::from("<a href=\"/\">phantom</a>");
+    for i in 0..segments.len() {
+        crumbs += &format!(
+            " / <a href=\"{}\">{}</a>",
+            href(&segments[..=i]),
+            escape(&segments[i])
+        );
+    }
+
+    let base = href(&segments);
+    let mut rows = String::new();
+    if !segments.is_empty() {
+        let up = href(&segments[..segments.len() - 1]);
+        rows += &format!(
+            "<tr><td colspan=\"3\"><a href=\"{}\">..</a></td></tr>\n",
+            up
+        );
+    }
+    for e in &entries {
+        let link = format!("{}{}", base, utf8_percent_encode(&e.name, NON_ALPHANUMERIC));
+        let (name, size) = if e.is_dir {
+            (
+                format!("<a href=\"{}/\">{}/</a>", link, escape(&e.name)),
+                String::new(),
+            )
+        } else {
+            // browsers can't play playlists, so stubs play their stream.
+            let (media, src) = match &e.media {
+                Some(media) => (media.content_type.as_str(), stream::url("", &media.id)),
+                None => (
+                    mime::content_type(&e.name, None).unwrap_or(mime::DEFAULT),
+                    link.clone(),
+                ),
+            };
+            let player = if media.starts_with("audio/") {
+                "audio"
+            } else if media.starts_with("video/") || media == "application/vnd.apple.mpegurl" {
+                "video"
+            } else {
+                ""
+            };
+            let play = if player.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " <button onclick=\"play(this, '{}', '{}')\">&#9654;</button>",
+                    player,
+                    escape(&src)
+                )
+            };
+            let name = format!("<a href=\"{}\">{}</a>{}", link, escape(&e.name), play);
+            (name, human_size(e.size))
+        };
+        let modified = e.modified.map(format_time).unwrap_or_default();
+        rows += &format!(
+            "<tr data-dir=\"{}\"><td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td></tr>\n",
+            e.is_dir as u8,
+            escape(&e.name.to_lowercase()),
+            name,
+            e.size,
+            size,
+            modified,
+            modified
+        );
+    }
+
+    Ok(format!(
+        include_str!("index.html"),
+        title = escape(&format!("/{}", segments.join("/"))),
+        crumbs = crumbs,
+        rows = rows
+    ))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", size)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// "YYYY-MM-DD HH:MM" in UTC, which also sorts as a string.
+fn format_time(t: SystemTime) -> String {
+    let t = Utc::new(t);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        t.year, t.month, t.day, t.hour, t.min
+    )
+}
@@ -1,56 +1,127 @@
-use crate::jellyfin::config::Config;
-use crate::jellyfin::file::File;
+use crate::jellyfin::config::{Config, Flavor};
+use crate::jellyfin::file::{Content, File};
+use crate::jellyfin::views::Folder;
+use crate::mime;
+use crate::options::Playlist;
+use crate::quota::Quota;
+use crate::stream::{self, Media};
+use crate::treefs::{self, Chunks, Entry};
 
 use bytes::Bytes;
 
-use reqwest::header::{HeaderMap};
-use reqwest::Client;
-use serde_json::Value::Array;
+use futures::StreamExt;
+use http::StatusCode;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, RANGE};
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value::{Array, Object};
 
 
 
 use std::time::{SystemTime};
+use webdav_handler::fs::{FsError, FsResult};
+
+// items summed up per request for the quota.
+const QUOTA_PAGE: usize = 1000;
 
 #[derive(Debug, Clone)]
 pub struct JellyfinClient {
     client: Client,
     config: Config,
+    flavor: Flavor,
+    playlist: Playlist,
+    // our own url, see `stream::url`.
+    stream_base: String,
 }
 
 impl JellyfinClient {
-    pub fn new(config: Config) -> JellyfinClient {
-        let headers = HeaderMap::new();
+    pub fn new(
+        mut config: Config,
+        flavor: Flavor,
+        playlist: Playlist,
+        stream_base: String,
+    ) -> JellyfinClient {
+        // all the urls below are built on the server url.
+        let server = config.server.trim_end_matches('/');
+        if !server.ends_with(flavor.prefix()) {
+            config.server = format!("{}{}", server, flavor.prefix());
+        }
+        let mut headers = HeaderMap::new();
+        let (name, value) = flavor.auth_header(&config.api_key);
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
         let client = Client::builder().default_headers(headers).build().unwrap();
 
-        JellyfinClient { client, config }
+        JellyfinClient {
+            client,
+            config,
+            flavor,
+            playlist,
+            stream_base,
+        }
+    }
+
+    /// Id of the folder served at the root, see `Config::views`.
+    pub fn root(&self) -> String {
+        if self.config.views {
+            Folder::Views.id()
+        } else {
+            self.config.root_folder_id.to_string()
+        }
     }
 
-    pub async fn opendir(&self, item_id: &str) -> Vec<File> {
+    pub fn flavor(&self) -> Flavor {
+        self.flavor
+    }
+
+    /// List the folder with the id `folder_id`, see `Folder`.
+    pub async fn opendir(&self, folder_id: &str) -> FsResult<Vec<Entry<File>>> {
         let config = &self.config;
+        let folder = Folder::parse(folder_id);
+        if folder == Folder::Views {
+            return Ok(Folder::views(config)
+                .into_iter()
+                .map(|(name, f)| Entry {
+                    key: File::folder(f.id()),
+                    name: name.to_owned(),
+                    size: Some(0),
+                    mtime: SystemTime::now(),
+                    ctime: SystemTime::now(),
+                    is_dir: true,
+                    etag: f.id(),
+                    content_type: "httpd/unix-directory".to_owned(),
+                    follows: None,
+                    media: None,
+                })
+                .collect());
+        }
+
         let url = format!(
-            "{}/Users/{}/Items?Fields=Path&ParentId={}&api_key={}",
-            config.server, config.user_id, item_id, config.api_key
+            "{}/{}&Fields=Path,Etag,DateModified,MediaStreams,Overview,ProviderIds&api_key={}",
+            config.server,
+            folder.query(config),
+            config.api_key
         );
-        let res: serde_json::Value = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .unwrap()
-            .json()
-            .await
-            .unwrap();
+        let res: serde_json::Value = self.get_json(url).await?;
 
         let mut files = Vec::new();
         match &res["Items"] {
             Array(data) => {
                 for d in data {
-                    let id = d["Id"].as_str().unwrap().to_string();
-                    //let mut name = d["Path"].as_str().unwrap().split("/").last().unwrap().to_string();
+                    let child = folder.child(d);
+                    let is_file = child.is_none();
+                    let id = match &child {
+                        Some(f) => f.id(),
+                        None => d["Id"].as_str().unwrap().to_string(),
+                    };
                     let mut name = d["Name"].as_str().unwrap().to_string();
+                    // movie folders are named "Title (Year)".
+                    let year = d["ProductionYear"].as_u64();
+                    if let (Some(Folder::Movie(_)), Some(year)) = (&child, year) {
+                        name = format!("{} ({})", name, year);
+                    }
                     let ctime = SystemTime::now();
-                    let is_file = !d["IsFolder"].as_bool().unwrap();
-                    let mut data: Option<String> = None;
+                    let etag = etag(d);
 
                     let size = if is_file {
                         /*
@@ -70,41 +141,315 @@ impl JellyfinClient {
                             format!("{}/Items/{}/Download?api_key={}", config.server, id, config.api_key)
                         };
                          */
-                        let url_data = format!("#EXTM3U\r\n#EXT-X-VERSION:7\r\n{}/Videos/{}/stream.mov?Static=true&mediaSourceId={}&api_key={}", config.server, id, id, config.api_key);
-                        let size = url_data.len();
-                        data = Some(url_data);
-                        size
+                        self.playlist(&stream_id(d)).len()
                     } else {
                         0
                     };
 
+                    let base = file_name(d, &config.naming);
                     if is_file {
-                        name = format!("{}.m3u8", name);
+                        name = format!("{}.{}", base, self.playlist.extension());
                     }
 
-                    let file = File {
-                        id,
+                    let container = field(d, "Container");
+                    let content_type = if is_file {
+                        mime::content_type(&name, container.as_deref())
+                            .or_else(|| d["MediaType"].as_str().and_then(mime::media_type))
+                            .unwrap_or(mime::DEFAULT)
+                    } else {
+                        "httpd/unix-directory"
+                    };
+                    // the stream of the item, which the playlist points at.
+                    let media = is_file.then(|| Media {
+                        id: stream_id(d),
+                        content_type: mime::content_type(&base, container.as_deref())
+                            .or_else(|| d["MediaType"].as_str().and_then(mime::media_type))
+                            .unwrap_or(mime::DEFAULT)
+                            .to_owned(),
+                    });
+
+                    let file = Entry {
+                        key: File {
+                            content: if is_file {
+                                Some(Content::Playlist(stream_id(d)))
+                            } else {
+                                None
+                            },
+                            id,
+                        },
                         name,
-                        size,
+                        size: Some(size as u64),
+                        mtime: ctime,
                         ctime,
-                        is_file,
-                        data,
+                        is_dir: !is_file,
+                        etag,
+                        content_type: content_type.to_owned(),
+                        follows: None,
+                        media,
                     };
 
                     tracing::info!(
                         "load file info: {} -> {} (size: {})",
-                        file.id,
+                        file.key.id,
                         file.name,
-                        file.size
+                        file.size.unwrap_or(0)
                     );
+                    let video = file.key.clone();
                     files.push(file);
+                    if is_file {
+                        files.extend(self.sidecars(d, &base, &folder, &video));
+                    }
                 }
             }
             _ => {}
         }
+
+        Ok(files)
+    }
+
+    // subtitles, artwork and .nfo files that go with the video `item`,
+    // named after its playlist `base`.m3u8 or `base`.strm, listed as `video`.
+    fn sidecars(
+        &self,
+        item: &serde_json::Value,
+        base: &str,
+        folder: &Folder,
+        video: &File,
+    ) -> Vec<Entry<File>> {
+        let id = item["Id"].as_str().unwrap_or("");
+        let tag = etag(item);
+        let mut files = Vec::new();
+        let mut add = |name: String, key: &str, content: Content| {
+            // the server reports no sizes for downloads, they are asked
+            // for once the file is used, see `size`.
+            let size = match &content {
+                Content::Text(text) => Some(text.len() as u64),
+                _ => None,
+            };
+            // the artwork of a movie has a name of its own.
+            let follows = name
+                .strip_prefix(base)
+                .filter(|rest| rest.starts_with(&['.', '-'][..]))
+                .map(|rest| (video.clone(), rest.to_owned()));
+            files.push(Entry {
+                key: File {
+                    id: format!("{}/{}", id, key),
+                    content: Some(content),
+                },
+                size,
+                content_type: mime::content_type(&name, None)
+                    .unwrap_or(mime::DEFAULT)
+                    .to_owned(),
+                name,
+                mtime: SystemTime::now(),
+                ctime: SystemTime::now(),
+                is_dir: false,
+                etag: format!("{}-{}", tag, key),
+                follows,
+                media: None,
+            });
+        };
+
+        // text subtitles can be converted, image based ones can't.
+        if let Array(streams) = &item["MediaStreams"] {
+            for s in streams {
+                if s["Type"] != "Subtitle" || s["IsTextSubtitleStream"] != true {
+                    continue;
+                }
+                let index = s["Index"].as_u64().unwrap_or(0);
+                let format = if s["Codec"] == "webvtt" { "vtt" } else { "srt" };
+                let mut name = base.to_owned();
+                if let Some(lang) = s["Language"].as_str() {
+                    name = format!("{}.{}", name, lang);
+                }
+                if s["IsForced"] == true {
+                    name = format!("{}.forced", name);
+                }
+                let name = format!("{}.{}", name, format);
+                let path = format!("Videos/{}/{}/Subtitles/{}/Stream.{}", id, id, index, format);
+                add(name, &format!("sub{}", index), Content::Download(path));
+            }
+        }
+
+        // a movie has its folder to itself, so the artwork can use the
+        // names media centers look for first.
+        let art = |kind: &str| match folder {
+            Folder::Movie(_) => format!("{}.jpg", kind),
+            _ => format!("{}-{}.jpg", base, kind),
+        };
+        if item["ImageTags"]["Primary"].is_string() {
+            let path = format!("Items/{}/Images/Primary?format=Jpg", id);
+            add(art("poster"), "poster", Content::Download(path));
+        }
+        if item["BackdropImageTags"][0].is_string() {
+            let path = format!("Items/{}/Images/Backdrop/0?format=Jpg", id);
+            add(art("fanart"), "fanart", Content::Download(path));
+        }
+
+        if self.config.nfo {
+            if let Some(nfo) = nfo(item) {
+                add(format!("{}.nfo", base), "nfo", Content::Text(nfo));
+            }
+        }
         files
     }
 
+    /// Read `len` bytes from `start` of the download at `path`, with a
+    /// ranged request.
+    pub async fn read(&self, path: &str, start: u64, len: usize) -> FsResult<Bytes> {
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+        let range = format!("bytes={}-{}", start, start + len as u64 - 1);
+        let res = self
+            .client
+            .get(self.download_url(path))
+            .header(RANGE, range)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        let res = match res {
+            Ok(res) if res.status() == StatusCode::PARTIAL_CONTENT => res.bytes().await,
+            // the server ignored the range and sent everything.
+            Ok(res) => res.bytes().await.map(|data| {
+                let start = (start as usize).min(data.len());
+                let end = (start + len).min(data.len());
+                data.slice(start..end)
+            }),
+            Err(e) if e.status() == Some(StatusCode::RANGE_NOT_SATISFIABLE) => Ok(Bytes::new()),
+            Err(e) => Err(e),
+        };
+        res.map_err(|e| {
+            tracing::error!("download failed! status: {:?}", e.status());
+            FsError::GeneralFailure
+        })
+    }
+
+    /// Stream the download at `path` from `start` to its end.
+    pub async fn open(&self, path: &str, start: u64) -> FsResult<Chunks> {
+        let res = self
+            .client
+            .get(self.download_url(path))
+            .header(RANGE, format!("bytes={}-", start))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        match res {
+            // the server ignored the range and sends everything.
+            Ok(res) if res.status() == StatusCode::OK => Ok(treefs::body(res, start)),
+            Ok(res) => Ok(treefs::body(res, 0)),
+            Err(e) if e.status() == Some(StatusCode::RANGE_NOT_SATISFIABLE) => {
+                Ok(futures::stream::empty().boxed())
+            }
+            Err(e) => {
+                tracing::error!("download failed! status: {:?}", e.status());
+                Err(FsError::GeneralFailure)
+            }
+        }
+    }
+
+    /// Size of the download at `path`, from a HEAD request. Converted
+    /// subtitles only answer GETs, their content is returned with the size.
+    pub async fn size(&self, path: &str) -> FsResult<(u64, Option<Bytes>)> {
+        let res = self.client.head(self.download_url(path)).send().await;
+        let size = res
+            .ok()
+            .filter(|r| r.status().is_success())
+            .and_then(|r| r.headers().get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok());
+        if let Some(size) = size {
+            return Ok((size, None));
+        }
+        let res = self
+            .client
+            .get(self.download_url(path))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        let res = match res {
+            Ok(res) => res.bytes().await,
+            Err(e) => Err(e),
+        };
+        res.map(|data| (data.len() as u64, Some(data))).map_err(|e| {
+            tracing::error!("download failed! status: {:?}", e.status());
+            FsError::GeneralFailure
+        })
+    }
+
+    fn download_url(&self, path: &str) -> String {
+        let config = &self.config;
+        let sep = if path.contains('?') { '&' } else { '?' };
+        format!("{}/{}{}api_key={}", config.server, path, sep, config.api_key)
+    }
+
+    /// Total size of the media in the library. Jellyfin doesn't report
+    /// the free space of its disks, so there's no total.
+    pub async fn quota(&self) -> FsResult<Quota> {
+        let config = &self.config;
+        let mut used = 0;
+        let mut start = 0;
+        loop {
+            let url = format!(
+                "{}/Users/{}/Items?Recursive=true&IsFolder=false&Fields=MediaSources&EnableImages=false&ParentId={}&StartIndex={}&Limit={}&api_key={}",
+                config.server, config.user_id, config.root_folder_id, start, QUOTA_PAGE, config.api_key
+            );
+            let res = self.get_json(url).await?;
+            let items = match &res["Items"] {
+                Array(items) => items,
+                _ => break,
+            };
+            for item in items {
+                // items with several versions are counted once.
+                used += item["MediaSources"][0]["Size"].as_u64().unwrap_or(0);
+            }
+            start += items.len();
+            let total = res["TotalRecordCount"].as_u64().unwrap_or(0) as usize;
+            if items.len() < QUOTA_PAGE || start >= total {
+                break;
+            }
+        }
+        Ok((used, None))
+    }
+
+    // urls carry the api key, so keep them out of the logs.
+    async fn get_json(&self, url: String) -> FsResult<serde_json::Value> {
+        let res = self.client.get(url).send().await.and_then(|r| r.error_for_status());
+        let res = match res {
+            Ok(res) => res.json().await,
+            Err(e) => Err(e),
+        };
+        res.map_err(|e| {
+            tracing::error!("request failed! status: {:?}", e.status());
+            FsError::GeneralFailure
+        })
+    }
+
+    /// Build the playlist or .strm file served for the video item `id`.
+    pub fn playlist(&self, id: &str) -> String {
+        self.playlist.content(&stream::url(&self.stream_base, id))
+    }
+
+    /// The request for the stream `id` of an item, see `stream_id`, which
+    /// its playlist points at. Authenticated by header, unlike the
+    /// `api_key` a player would have to be given.
+    pub fn stream(&self, id: &str) -> FsResult<RequestBuilder> {
+        let (audio, id) = match id.strip_prefix(AUDIO) {
+            Some(id) => (true, id),
+            None => (false, id),
+        };
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(FsError::NotFound);
+        }
+        let url = if audio {
+            format!("{}/Audio/{}/stream?static=true", self.config.server, id)
+        } else {
+            format!(
+                "{}/Videos/{}/stream.mov?Static=true&mediaSourceId={}",
+                self.config.server, id, id
+            )
+        };
+        Ok(self.client.get(url))
+    }
+
     async fn download(&self, id: &str, start: usize, end: usize) -> Bytes {
         tracing::info!("call download: {}, {}-{}", id, start, end);
         let config = &self.config;
@@ -128,3 +473,125 @@ impl JellyfinClient {
         Bytes::from(url)
     }
 }
+
+// prefix of the stream ids of audio items.
+const AUDIO: &str = "audio:";
+
+// id of the stream of a media item, its own id for videos, which are
+// streamed from another endpoint than music.
+fn stream_id(item: &serde_json::Value) -> String {
+    let id = item["Id"].as_str().unwrap_or("");
+    match item["MediaType"].as_str() {
+        Some("Audio") => format!("{}{}", AUDIO, id),
+        _ => id.to_owned(),
+    }
+}
+
+// name of the media file of a video item, following the `naming` mode of
+// the config. Templates that refer to fields the item doesn't have, like
+// a season number for a movie, fall back to the file name.
+fn file_name(item: &serde_json::Value, naming: &str) -> String {
+    let name = item["Name"].as_str().unwrap_or("");
+    let path_name = || {
+        item["Path"]
+            .as_str()
+            .and_then(|p| p.rsplit(['/', '\\']).next())
+            .filter(|p| !p.is_empty())
+            .unwrap_or(name)
+            .to_string()
+    };
+    match naming {
+        "name" => name.to_string(),
+        "path" => path_name(),
+        template => render(template, item).unwrap_or_else(path_name),
+    }
+}
+
+// fill in the `{Field}` placeholders of a naming template.
+fn render(template: &str, item: &serde_json::Value) -> Option<String> {
+    let mut res = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        res.push_str(&rest[..start]);
+        res.push_str(&field(item, &rest[start + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+    Some(res)
+}
+
+fn field(item: &serde_json::Value, name: &str) -> Option<String> {
+    match name {
+        "Season" => item["ParentIndexNumber"].as_u64().map(|n| format!("{:02}", n)),
+        "Episode" => item["IndexNumber"].as_u64().map(|n| format!("{:02}", n)),
+        // containers can be a list, like "mov,mp4,m4a".
+        "Container" => item["Container"].as_str()?.split(',').next().map(|c| c.to_string()),
+        _ => match &item[name] {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        },
+    }
+}
+
+// kodi style .nfo for movies and episodes.
+fn nfo(item: &serde_json::Value) -> Option<String> {
+    let (root, fields): (&str, &[(&str, &str)]) = match item["Type"].as_str()? {
+        "Movie" => (
+            "movie",
+            &[("title", "Name"), ("year", "ProductionYear"), ("plot", "Overview")],
+        ),
+        "Episode" => (
+            "episodedetails",
+            &[
+                ("title", "Name"),
+                ("showtitle", "SeriesName"),
+                ("season", "ParentIndexNumber"),
+                ("episode", "IndexNumber"),
+                ("plot", "Overview"),
+            ],
+        ),
+        _ => return None,
+    };
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<{}>\n",
+        root
+    );
+    for (tag, key) in fields {
+        if let Some(value) = field(item, key) {
+            xml += &format!("  <{}>{}</{}>\n", tag, escape(&value), tag);
+        }
+    }
+    if let Object(ids) = &item["ProviderIds"] {
+        for (provider, id) in ids {
+            if let Some(id) = id.as_str() {
+                let provider = escape(&provider.to_lowercase());
+                xml += &format!("  <uniqueid type=\"{}\">{}</uniqueid>\n", provider, escape(id));
+            }
+        }
+    }
+    xml += &format!("</{}>\n", root);
+    Some(xml)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// jellyfin's Etag changes whenever the item is updated. Fall back to
+// the modification date for items without one.
+fn etag(item: &serde_json::Value) -> String {
+    let tag = match item["Etag"].as_str() {
+        Some(tag) => tag,
+        None => item["DateModified"].as_str().unwrap_or(""),
+    };
+    let tag = tag
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>();
+    format!("{}-{}", item["Id"].as_str().unwrap_or(""), tag)
+}
@@ -1,5 +1,40 @@
+use reqwest::header::{HeaderName, AUTHORIZATION};
 use serde::{Deserialize, Serialize};
 
+/// The servers speaking the Jellyfin API. Emby is where Jellyfin was
+/// forked from, and mostly differs in how requests are authenticated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Jellyfin,
+    Emby,
+}
+
+impl Flavor {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flavor::Jellyfin => "jellyfin",
+            Flavor::Emby => "emby",
+        }
+    }
+
+    /// Prefix of the API paths on the server.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Flavor::Jellyfin => "",
+            Flavor::Emby => "/emby",
+        }
+    }
+
+    /// Header carrying the api key. Urls handed to players keep the
+    /// `api_key` parameter, which both servers accept.
+    pub fn auth_header(&self, api_key: &str) -> (HeaderName, String) {
+        match self {
+            Flavor::Jellyfin => (AUTHORIZATION, format!("MediaBrowser Token=\"{}\"", api_key)),
+            Flavor::Emby => (HeaderName::from_static("x-emby-token"), api_key.to_owned()),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct Config {
     pub server: String,
@@ -7,4 +42,16 @@ pub struct Config {
     pub root_folder_id: String,
     pub api_key: String,
     pub bitrate: u32,
+    /// How video files are named: `name` for the display name, `path`
+    /// for the file name on the server, or a template like
+    /// `{SeriesName} - S{Season}E{Episode} - {Name}.{Container}`.
+    #[serde(default)]
+    pub naming: String,
+    /// Show the virtual `Movies`, `Shows`, `Music`, `Playlists` and
+    /// `Collections` folders at the root, instead of `root_folder_id`.
+    #[serde(default)]
+    pub views: bool,
+    /// Also generate Kodi style .nfo files for movies and episodes.
+    #[serde(default)]
+    pub nfo: bool,
 }
@@ -1,10 +1,65 @@
-use std::time::SystemTime;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
+use crate::treefs::Key;
+
+/// An item or a folder of the server, or a file that goes with an item,
+/// as the key of its node. Keys are told apart by their id alone, the
+/// content only says how to read a file.
+#[derive(Debug, Clone)]
 pub struct File {
+    /// The item id, a `Folder` id, or `<item id>/<kind>` for sidecars.
     pub id: String,
-    pub name: String,
-    pub size: usize,
-    pub ctime: SystemTime,
-    pub is_file: bool,
-    pub data: Option<String>,
+    /// Where the content of a file comes from, None for directories.
+    pub content: Option<Content>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Content {
+    /// The playlist or .strm file of a media item, with the id of its
+    /// stream, see `JellyfinClient::stream`.
+    Playlist(String),
+    /// A file downloaded from a path on the server, like an image.
+    Download(String),
+    /// Generated from the item's metadata, like a .nfo file.
+    Text(String),
+}
+
+impl File {
+    pub fn folder(id: String) -> File {
+        File { id, content: None }
+    }
+}
+
+impl PartialEq for File {
+    fn eq(&self, other: &File) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for File {}
+
+impl Hash for File {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl PartialOrd for File {
+    fn partial_cmp(&self, other: &File) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for File {
+    fn cmp(&self, other: &File) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl Key for File {
+    fn as_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.id)
+    }
 }
@@ -1,4 +1,5 @@
 mod client;
-mod config;
+pub mod config;
 mod file;
 pub mod fs;
+mod views;
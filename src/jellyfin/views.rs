@@ -0,0 +1,103 @@
+use crate::jellyfin::config::Config;
+
+/// A folder of the filesystem. Besides plain Jellyfin items there is a
+/// virtual tree (see `Config::views`) that is built from queries over the
+/// whole library. Folders are identified by strings, used in place of
+/// item ids: `movies`, `movie:<item id>`, or just `<item id>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Folder {
+    /// The children of a Jellyfin item, like a library, series, season,
+    /// album, playlist or box set.
+    Item(String),
+    /// The root of the virtual tree.
+    Views,
+    /// All movies, each in a folder of its own.
+    Movies,
+    /// The folder of one movie.
+    Movie(String),
+    /// All series. Their seasons and episodes are plain items.
+    Shows,
+    /// All album artists.
+    Music,
+    /// The albums of an artist.
+    Artist(String),
+    Playlists,
+    Collections,
+}
+
+impl Folder {
+    pub fn parse(id: &str) -> Folder {
+        match id.split_once(':') {
+            Some(("movie", id)) => Folder::Movie(id.to_owned()),
+            Some(("artist", id)) => Folder::Artist(id.to_owned()),
+            _ => match id {
+                "views" => Folder::Views,
+                "movies" => Folder::Movies,
+                "shows" => Folder::Shows,
+                "music" => Folder::Music,
+                "playlists" => Folder::Playlists,
+                "collections" => Folder::Collections,
+                _ => Folder::Item(id.to_owned()),
+            },
+        }
+    }
+
+    pub fn id(&self) -> String {
+        match self {
+            Folder::Item(id) => id.clone(),
+            Folder::Views => "views".to_owned(),
+            Folder::Movies => "movies".to_owned(),
+            Folder::Movie(id) => format!("movie:{}", id),
+            Folder::Shows => "shows".to_owned(),
+            Folder::Music => "music".to_owned(),
+            Folder::Artist(id) => format!("artist:{}", id),
+            Folder::Playlists => "playlists".to_owned(),
+            Folder::Collections => "collections".to_owned(),
+        }
+    }
+
+    /// The folders at the root of the virtual tree, by name. The library
+    /// as Jellyfin structures it is still available under `Library`.
+    pub fn views(config: &Config) -> Vec<(&'static str, Folder)> {
+        vec![
+            ("Movies", Folder::Movies),
+            ("Shows", Folder::Shows),
+            ("Music", Folder::Music),
+            ("Playlists", Folder::Playlists),
+            ("Collections", Folder::Collections),
+            ("Library", Folder::Item(config.root_folder_id.clone())),
+        ]
+    }
+
+    /// Path and query of the API call listing the children of the folder.
+    pub fn query(&self, config: &Config) -> String {
+        let items = format!("Users/{}/Items?", config.user_id);
+        match self {
+            Folder::Item(id) => format!("{}ParentId={}", items, id),
+            Folder::Views => unreachable!("the views are not listed from jellyfin"),
+            Folder::Movies => format!("{}IncludeItemTypes=Movie&Recursive=true", items),
+            Folder::Movie(id) => format!("{}Ids={}", items, id),
+            Folder::Shows => format!("{}IncludeItemTypes=Series&Recursive=true", items),
+            // artists are not part of the item tree.
+            Folder::Music => format!("Artists/AlbumArtists?UserId={}", config.user_id),
+            Folder::Artist(id) => format!(
+                "{}IncludeItemTypes=MusicAlbum&Recursive=true&AlbumArtistIds={}",
+                items, id
+            ),
+            Folder::Playlists => format!("{}IncludeItemTypes=Playlist&Recursive=true", items),
+            Folder::Collections => format!("{}IncludeItemTypes=BoxSet&Recursive=true", items),
+        }
+    }
+
+    /// The folder for `item`, listed in this folder, or None if the item
+    /// is served as a file.
+    pub fn child(&self, item: &serde_json::Value) -> Option<Folder> {
+        let id = item["Id"].as_str().unwrap_or("").to_owned();
+        match self {
+            Folder::Movies => Some(Folder::Movie(id)),
+            Folder::Music => Some(Folder::Artist(id)),
+            _ if item["IsFolder"].as_bool() == Some(true) => Some(Folder::Item(id)),
+            _ => None,
+        }
+    }
+}
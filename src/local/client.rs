@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use webdav_handler::fs::{FsError, FsResult};
+
+use crate::mime;
+use crate::treefs::Entry;
+
+#[derive(Debug, Clone)]
+pub struct LocalClient {
+    roots: Vec<PathBuf>,
+}
+
+impl LocalClient {
+    pub fn new(roots: Vec<PathBuf>) -> LocalClient {
+        LocalClient { roots }
+    }
+
+    /// List the directory `dir`, a path relative to the roots, empty for
+    /// the roots themselves. Directories at the same path in several
+    /// roots are merged, of other entries with the same name the one in
+    /// the first root is listed.
+    pub async fn opendir(&self, dir: &Path) -> FsResult<Vec<Entry<PathBuf>>> {
+        let mut files = Vec::new();
+        let mut names = HashSet::new();
+        let mut found = false;
+        for root in &self.roots {
+            let mut entries = match tokio::fs::read_dir(root.join(dir)).await {
+                Ok(entries) => entries,
+                // not every root has every directory.
+                Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+            found = true;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                // follow symlinks, and skip the ones that are broken.
+                let meta = match tokio::fs::metadata(&path).await {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        tracing::warn!("skipping {}: {}", path.display(), e);
+                        continue;
+                    }
+                };
+                if !meta.is_dir() && !meta.is_file() {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if names.insert(name.clone()) {
+                    files.push(file(dir.join(&name), name, &meta));
+                }
+            }
+        }
+        if !found {
+            return Err(FsError::NotFound);
+        }
+        Ok(files)
+    }
+
+    /// Where the file `path` is on disk, in the first root that has it.
+    pub async fn locate(&self, path: &Path) -> FsResult<PathBuf> {
+        for root in &self.roots {
+            let full = root.join(path);
+            match tokio::fs::metadata(&full).await {
+                Ok(meta) if meta.is_file() => return Ok(full),
+                _ => continue,
+            }
+        }
+        Err(FsError::NotFound)
+    }
+}
+
+fn file(path: PathBuf, name: String, meta: &Metadata) -> Entry<PathBuf> {
+    let mtime = meta.modified().unwrap_or(UNIX_EPOCH);
+    let secs = mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let content_type = if meta.is_dir() {
+        "httpd/unix-directory".to_owned()
+    } else {
+        mime::content_type(&name, None)
+            .unwrap_or(mime::DEFAULT)
+            .to_owned()
+    };
+    Entry {
+        etag: format!("{:x}-{:x}", meta.len(), secs),
+        size: Some(meta.len()),
+        mtime,
+        ctime: meta.created().unwrap_or(mtime),
+        is_dir: meta.is_dir(),
+        content_type,
+        key: path,
+        name,
+        follows: None,
+        media: None,
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use futures::future::FutureExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use webdav_handler::fs::FsFuture;
+
+use crate::local::client::LocalClient;
+use crate::options::Options;
+use crate::props::PropStore;
+use crate::treefs::{Backend, Entry, TreeFs};
+
+/// Serves directories on disk, read only. With several roots, their
+/// contents are merged, directories with the same path into one. File
+/// content is read from disk as requested, only the tree is cached.
+pub type LocalFS = TreeFs<LocalClient>;
+
+impl LocalFS {
+    /// Create a new "LocalFS" filesystem serving `roots`.
+    pub fn new(options: &Options, props: PropStore, roots: Vec<PathBuf>) -> Box<LocalFS> {
+        let client = LocalClient::new(roots);
+        Box::new(TreeFs::with_backend(client, options, props))
+    }
+}
+
+impl Backend for LocalClient {
+    // files are keyed by their path relative to the roots, the merged
+    // roots by an empty path.
+    type Key = PathBuf;
+
+    fn root(&self) -> PathBuf {
+        PathBuf::new()
+    }
+
+    fn opendir<'a>(&'a self, dir: &'a PathBuf) -> FsFuture<'a, Vec<Entry<PathBuf>>> {
+        LocalClient::opendir(self, dir).boxed()
+    }
+
+    fn read<'a>(&'a self, path: &'a PathBuf, start: u64, len: usize) -> FsFuture<'a, Bytes> {
+        async move {
+            let mut file = tokio::fs::File::open(self.locate(path).await?).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut buf = Vec::with_capacity(len);
+            file.take(len as u64).read_to_end(&mut buf).await?;
+            Ok(Bytes::from(buf))
+        }
+        .boxed()
+    }
+
+    fn prop_prefix(&self) -> &'static str {
+        "local"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::fs;
+    use webdav_handler::davpath::DavPath;
+    use webdav_handler::fs::{DavFileSystem, OpenOptions, ReadDirMeta};
+
+    use crate::names::Sanitize;
+    use crate::options::Playlist;
+
+    #[tokio::test]
+    async fn list_and_read() {
+        let root = std::env::temp_dir().join(format!("phantom-local-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a b.txt"), "hello world").unwrap();
+        let options = Options {
+            cache_size: 1024 * 1024,
+            max_nodes: 1000,
+            sanitize: Sanitize::Minimal,
+            playlist: Playlist::M3u8,
+            stream_base: "http://127.0.0.1:4918".to_owned(),
+        };
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let fs = LocalFS::new(&options, PropStore::new(db), vec![root.clone()]);
+
+        let dir = DavPath::new("/sub/").unwrap();
+        let mut entries = fs.read_dir(&dir, ReadDirMeta::Data).await.unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next().await {
+            let meta = entry.metadata().await.unwrap();
+            names.push((String::from_utf8(entry.name()).unwrap(), meta.len()));
+        }
+        assert_eq!(names, vec![("a b.txt".to_owned(), 11)]);
+
+        let path = DavPath::new("/sub/a%20b.txt").unwrap();
+        let options = OpenOptions {
+            read: true,
+            ..Default::default()
+        };
+        let mut file = fs.open(&path, options).await.unwrap();
+        file.seek(SeekFrom::Start(6)).await.unwrap();
+        assert_eq!(file.read_bytes(100).await.unwrap(), Bytes::from("world"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::FutureExt;
+use reqwest::RequestBuilder;
+use webdav_handler::fs::FsFuture;
+
+use crate::local::client::LocalClient;
+use crate::quota::Quota;
+use crate::treefs::{Backend, Chunks, Entry, Key};
+
+/// Another backend with directories on disk merged into its root, next to
+/// its own entries.
+#[derive(Debug)]
+pub struct Hybrid<B> {
+    remote: B,
+    local: LocalClient,
+}
+
+/// A key of either side of a `Hybrid`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HybridKey<K> {
+    Remote(K),
+    Local(PathBuf),
+}
+
+impl<K: Key> Key for HybridKey<K> {
+    fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            HybridKey::Remote(key) => key.as_text(),
+            HybridKey::Local(path) => path.as_text(),
+        }
+    }
+}
+
+impl<B: Backend> Hybrid<B> {
+    pub fn new(remote: B, roots: Vec<PathBuf>) -> Hybrid<B> {
+        Hybrid {
+            remote,
+            local: LocalClient::new(roots),
+        }
+    }
+}
+
+impl<B: Backend> Backend for Hybrid<B> {
+    type Key = HybridKey<B::Key>;
+
+    const QUOTA_TTL: Duration = B::QUOTA_TTL;
+
+    fn root(&self) -> Self::Key {
+        HybridKey::Remote(self.remote.root())
+    }
+
+    fn opendir<'a>(&'a self, dir: &'a Self::Key) -> FsFuture<'a, Vec<Entry<Self::Key>>> {
+        async move {
+            let key = match dir {
+                HybridKey::Remote(key) => key,
+                HybridKey::Local(path) => {
+                    let entries = self.local.opendir(path).await?;
+                    return Ok(entries.into_iter().map(local).collect());
+                }
+            };
+            let entries = self.remote.opendir(key).await?;
+            let mut entries = entries.into_iter().map(remote).collect::<Vec<_>>();
+            if *key == self.remote.root() {
+                // a missing root shouldn't hide the backend.
+                match self.local.opendir(Path::new("")).await {
+                    Ok(local_entries) => entries.extend(local_entries.into_iter().map(local)),
+                    Err(e) => tracing::warn!("failed to list the local roots: {:?}", e),
+                }
+            }
+            Ok(entries)
+        }
+        .boxed()
+    }
+
+    fn read<'a>(&'a self, key: &'a Self::Key, start: u64, len: usize) -> FsFuture<'a, Bytes> {
+        match key {
+            HybridKey::Remote(key) => self.remote.read(key, start, len),
+            HybridKey::Local(path) => self.local.read(path, start, len),
+        }
+    }
+
+    fn open<'a>(&'a self, key: &'a Self::Key, start: u64) -> FsFuture<'a, Option<Chunks>> {
+        match key {
+            HybridKey::Remote(key) => self.remote.open(key, start),
+            HybridKey::Local(path) => self.local.open(path, start),
+        }
+    }
+
+    fn size<'a>(&'a self, key: &'a Self::Key) -> FsFuture<'a, (u64, Option<Bytes>)> {
+        match key {
+            HybridKey::Remote(key) => self.remote.size(key),
+            HybridKey::Local(path) => self.local.size(path),
+        }
+    }
+
+    fn prop_prefix(&self) -> &'static str {
+        self.remote.prop_prefix()
+    }
+
+    // each side keeps the properties it has on its own.
+    fn prop_key(&self, key: &Self::Key) -> String {
+        match key {
+            HybridKey::Remote(key) => self.remote.prop_key(key),
+            HybridKey::Local(path) => self.local.prop_key(path),
+        }
+    }
+
+    fn quota(&self) -> FsFuture<'_, Quota> {
+        self.remote.quota()
+    }
+
+    fn stream<'a>(&'a self, id: &'a str) -> FsFuture<'a, RequestBuilder> {
+        self.remote.stream(id)
+    }
+}
+
+fn remote<K>(entry: Entry<K>) -> Entry<HybridKey<K>> {
+    map_key(entry, HybridKey::Remote)
+}
+
+fn local<K>(entry: Entry<PathBuf>) -> Entry<HybridKey<K>> {
+    map_key(entry, HybridKey::Local)
+}
+
+fn map_key<K, L>(entry: Entry<K>, f: impl Fn(K) -> L) -> Entry<L> {
+    Entry {
+        key: f(entry.key),
+        name: entry.name,
+        size: entry.size,
+        mtime: entry.mtime,
+        ctime: entry.ctime,
+        is_dir: entry.is_dir,
+        etag: entry.etag,
+        content_type: entry.content_type,
+        follows: entry.follows.map(|(key, rest)| (f(key), rest)),
+        media: entry.media,
+    }
+}
@@ -0,0 +1,3 @@
+mod client;
+pub mod fs;
+pub mod hybrid;
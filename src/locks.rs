@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::ls::{DavLock, DavLockSystem};
+use xmltree::Element;
+
+// locks without a timeout, or with a longer one, expire after this.
+const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// WebDAV lock system with exclusive and shared locks, timeouts and
+/// depth-infinity locks on collections.
+///
+/// Locks are kept in memory, and optionally also in a sled tree so
+/// they survive a restart.
+#[derive(Debug, Clone)]
+pub struct LockSystem {
+    locks: Arc<Mutex<Vec<DavLock>>>,
+    store: Option<sled::Tree>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredLock {
+    token: String,
+    path: String,
+    principal: Option<String>,
+    owner: Option<Vec<u8>>,
+    timeout_at: u64,
+    timeout: u64,
+    shared: bool,
+    deep: bool,
+}
+
+impl LockSystem {
+    /// Create a new lock system. If `store` is set, existing locks are
+    /// loaded from it and all changes are written back.
+    pub fn new(store: Option<sled::Tree>) -> Box<LockSystem> {
+        let mut locks = Vec::new();
+        if let Some(store) = &store {
+            for (_, value) in store.iter().flatten() {
+                match serde_json::from_slice::<StoredLock>(&value) {
+                    Ok(stored) => locks.extend(stored.into_lock()),
+                    Err(e) => tracing::error!("skipping invalid stored lock: {}", e),
+                }
+            }
+        }
+        let ls = LockSystem {
+            locks: Arc::new(Mutex::new(locks)),
+            store,
+        };
+        ls.expire(&mut ls.locks.lock().unwrap());
+        Box::new(ls)
+    }
+
+    // drop locks that timed out.
+    fn expire(&self, locks: &mut Vec<DavLock>) {
+        let now = SystemTime::now();
+        let (expired, valid) = locks
+            .drain(..)
+            .partition::<Vec<_>, _>(|l| l.timeout_at.map(|t| t <= now).unwrap_or(false));
+        *locks = valid;
+        for lock in expired {
+            tracing::info!("lock {} on {} expired", lock.token, lock.path);
+            self.forget(&lock);
+        }
+    }
+
+    fn save(&self, lock: &DavLock) {
+        if let Some(store) = &self.store {
+            let value = serde_json::to_vec(&StoredLock::from_lock(lock)).unwrap();
+            match store.insert(&lock.token, value) {
+                Ok(_) => flush(store),
+                Err(e) => tracing::error!("failed to store lock {}: {}", lock.token, e),
+            }
+        }
+    }
+
+    fn forget(&self, lock: &DavLock) {
+        if let Some(store) = &self.store {
+            match store.remove(&lock.token) {
+                Ok(_) => flush(store),
+                Err(e) => tracing::error!("failed to remove lock {}: {}", lock.token, e),
+            }
+        }
+    }
+}
+
+// write the lock store to disk in the background, the lock system's
+// methods aren't async and run on the runtime.
+fn flush(store: &sled::Tree) {
+    let store = store.clone();
+    tokio::spawn(async move {
+        if let Err(e) = store.flush_async().await {
+            tracing::error!("failed to flush locks: {}", e);
+        }
+    });
+}
+
+impl DavLockSystem for LockSystem {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        shared: bool,
+        deep: bool,
+    ) -> Result<DavLock, DavLock> {
+        let locks = &mut *self.locks.lock().unwrap();
+        self.expire(locks);
+
+        // shared locks only conflict with exclusive ones.
+        for l in locks.iter() {
+            let overlaps = covers(l, path) || (deep && is_below(&l.path, path));
+            if overlaps && !(shared && l.shared) {
+                return Err(l.clone());
+            }
+        }
+
+        let timeout = Some(timeout.unwrap_or(MAX_TIMEOUT).min(MAX_TIMEOUT));
+        let lock = DavLock {
+            token: Uuid::new_v4().to_urn().to_string(),
+            path: path.clone(),
+            principal: principal.map(|s| s.to_string()),
+            owner: owner.cloned(),
+            timeout_at: timeout.map(|d| SystemTime::now() + d),
+            timeout,
+            shared,
+            deep,
+        };
+        self.save(&lock);
+        locks.push(lock.clone());
+        Ok(lock)
+    }
+
+    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
+        let locks = &mut *self.locks.lock().unwrap();
+        self.expire(locks);
+        let idx = locks
+            .iter()
+            .position(|l| l.token == token && is_below(path, &l.path))
+            .ok_or(())?;
+        let lock = locks.remove(idx);
+        self.forget(&lock);
+        Ok(())
+    }
+
+    fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()> {
+        let locks = &mut *self.locks.lock().unwrap();
+        self.expire(locks);
+        let lock = locks
+            .iter_mut()
+            .find(|l| l.token == token && is_below(path, &l.path))
+            .ok_or(())?;
+        lock.timeout = Some(timeout.unwrap_or(MAX_TIMEOUT).min(MAX_TIMEOUT));
+        lock.timeout_at = lock.timeout.map(|d| SystemTime::now() + d);
+        let lock = lock.clone();
+        self.save(&lock);
+        Ok(lock)
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock> {
+        let locks = &mut *self.locks.lock().unwrap();
+        self.expire(locks);
+
+        let held = |l: &DavLock| {
+            submitted_tokens.iter().any(|t| *t == l.token)
+                && (ignore_principal || principal == l.principal.as_deref())
+        };
+
+        // locks on the path itself or inherited from a parent collection.
+        let mut holds_lock = false;
+        let mut first_shared = None;
+        for l in locks.iter().filter(|l| covers(l, path)) {
+            if held(l) {
+                holds_lock = true;
+            } else if !l.shared {
+                return Err(l.clone());
+            } else if first_shared.is_none() {
+                first_shared = Some(l);
+            }
+        }
+        if let (false, Some(l)) = (holds_lock, first_shared) {
+            return Err(l.clone());
+        }
+
+        // for collections, all locks further down must be ours as well.
+        if deep {
+            if let Some(l) = locks
+                .iter()
+                .find(|l| is_below(&l.path, path) && !covers(l, path) && !held(l))
+            {
+                return Err(l.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        let locks = &mut *self.locks.lock().unwrap();
+        self.expire(locks);
+        locks.iter().filter(|l| covers(l, path)).cloned().collect()
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        let locks = &mut *self.locks.lock().unwrap();
+        let (deleted, kept) = locks
+            .drain(..)
+            .partition::<Vec<_>, _>(|l| is_below(&l.path, path));
+        *locks = kept;
+        for lock in deleted {
+            self.forget(&lock);
+        }
+        Ok(())
+    }
+}
+
+impl StoredLock {
+    fn from_lock(lock: &DavLock) -> StoredLock {
+        let owner = lock.owner.as_ref().and_then(|o| {
+            let mut buf = Vec::new();
+            o.write(&mut buf).ok().map(|_| buf)
+        });
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        StoredLock {
+            token: lock.token.clone(),
+            path: lock.path.as_url_string(),
+            principal: lock.principal.clone(),
+            owner,
+            timeout_at: lock.timeout_at.map(secs).unwrap_or(0),
+            timeout: lock.timeout.map(|d| d.as_secs()).unwrap_or(0),
+            shared: lock.shared,
+            deep: lock.deep,
+        }
+    }
+
+    fn into_lock(self) -> Option<DavLock> {
+        let owner = match self.owner {
+            Some(xml) => Some(Element::parse(&xml[..]).ok()?),
+            None => None,
+        };
+        Some(DavLock {
+            token: self.token,
+            path: DavPath::new(&self.path).ok()?,
+            principal: self.principal,
+            owner,
+            timeout_at: Some(UNIX_EPOCH + Duration::from_secs(self.timeout_at)),
+            timeout: Some(Duration::from_secs(self.timeout)),
+            shared: self.shared,
+            deep: self.deep,
+        })
+    }
+}
+
+// does the lock apply to the path, either directly or through a
+// depth-infinity lock on a parent collection?
+fn covers(lock: &DavLock, path: &DavPath) -> bool {
+    if lock.deep {
+        is_below(path, &lock.path)
+    } else {
+        segments(path) == segments(&lock.path)
+    }
+}
+
+// is `path` equal to or below `parent`?
+fn is_below(path: &DavPath, parent: &DavPath) -> bool {
+    let path = segments(path);
+    let parent = segments(parent);
+    path.len() >= parent.len() && path[..parent.len()] == parent[..]
+}
+
+fn segments(path: &DavPath) -> Vec<&[u8]> {
+    path.as_bytes()
+        .split(|&c| c == b'/')
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // whether the lock is granted.
+    fn lock(ls: &LockSystem, path: &str, shared: bool, deep: bool) -> bool {
+        let path = DavPath::new(path).unwrap();
+        ls.lock(&path, None, None, None, shared, deep).is_ok()
+    }
+
+    #[test]
+    fn shared_locks_only_conflict_with_exclusive_ones() {
+        let ls = LockSystem::new(None);
+        assert!(lock(&ls, "/a", true, false));
+        assert!(lock(&ls, "/a", true, false));
+        assert!(!lock(&ls, "/a", false, false));
+
+        assert!(lock(&ls, "/b", false, false));
+        assert!(!lock(&ls, "/b", true, false));
+        assert!(!lock(&ls, "/b", false, false));
+    }
+
+    #[test]
+    fn deep_locks_cover_descendants() {
+        let ls = LockSystem::new(None);
+        assert!(lock(&ls, "/c/", false, true));
+        assert!(!lock(&ls, "/c/d", false, false));
+
+        // a lock below a collection blocks a deep lock on it.
+        assert!(lock(&ls, "/e/f", false, false));
+        assert!(!lock(&ls, "/e/", false, true));
+        assert!(lock(&ls, "/e/", false, false));
+    }
+
+    #[test]
+    fn check_needs_the_token() {
+        let ls = LockSystem::new(None);
+        let path = DavPath::new("/a").unwrap();
+        let l = ls.lock(&path, None, None, None, false, false).unwrap();
+        assert!(ls.check(&path, None, false, false, vec![]).is_err());
+        assert!(ls.check(&path, None, false, false, vec![&l.token]).is_ok());
+        assert!(ls.unlock(&path, &l.token).is_ok());
+        assert!(ls.check(&path, None, false, false, vec![]).is_ok());
+    }
+}
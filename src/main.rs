@@ -1,17 +1,56 @@
+mod cache;
+mod dlna;
+mod flight;
+mod ftp;
+mod fuse;
+mod index;
 mod jellyfin;
+mod local;
+mod locks;
+mod mime;
+mod names;
 mod oof;
+mod options;
+mod plex;
+mod props;
+mod quota;
+mod s3;
+mod stream;
+mod time;
 mod tree;
+mod treefs;
+mod webdav;
 
-use oof::client::ClientOof;
-
-use clap::{crate_version, App, Arg};
+use clap::{crate_version, App, Arg, SubCommand};
 use oof::oof_fs::OofFS;
 use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use crate::jellyfin::config::Flavor;
 use crate::jellyfin::fs::JellyfinFS;
-use webdav_handler::fs::DavFileSystem;
+use crate::local::fs::LocalFS;
+use crate::local::hybrid::Hybrid;
+use crate::fuse::Inodes;
+use crate::locks::LockSystem;
+use crate::mime::ContentTypes;
+use crate::options::Options;
+use crate::plex::fs::PlexFS;
+use crate::props::PropStore;
+use crate::s3::fs::S3FS;
+use crate::stream::Streams;
+use crate::treefs::{Backend, TreeFs};
+use crate::webdav::fs::WebDavFS;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::ls::DavLockSystem;
 use webdav_handler::{fakels::FakeLs, DavHandler};
 
+/// What the frontends need from a filesystem, besides serving it.
+pub trait PhantomFs: Inodes + ContentTypes + Streams {}
+
+impl<T: Inodes + ContentTypes + Streams> PhantomFs for T {}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
@@ -24,40 +63,225 @@ async fn main() {
                 .default_value("4918")
                 .help("webdav server port"),
         )
+        .arg(
+            Arg::with_name("bind")
+                .long("bind")
+                .default_value("127.0.0.1")
+                .help("address to listen on, 0.0.0.0 for all interfaces"),
+        )
         .arg(
             Arg::with_name("type")
                 .short("t")
                 .default_value("oof")
-                .help("FS type, oof or jellyfin"),
+                .help("FS type, oof, jellyfin, emby, plex, local, webdav or s3"),
+        )
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("directory served by the local FS type, or merged into the root of the others, repeat to merge several"),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .default_value("64")
+                .help("file content cache size in MiB"),
+        )
+        .arg(
+            Arg::with_name("max-nodes")
+                .long("max-nodes")
+                .default_value("200000")
+                .help("max number of cached directory entries"),
+        )
+        .arg(
+            Arg::with_name("sanitize")
+                .long("sanitize")
+                .possible_values(&["minimal", "windows"])
+                .default_value("minimal")
+                .help("replace characters in file names that are invalid in paths, or on windows"),
+        )
+        .arg(
+            Arg::with_name("playlist")
+                .long("playlist")
+                .possible_values(&["m3u8", "strm"])
+                .default_value("m3u8")
+                .help("serve videos as m3u8 playlists, or as .strm files for Kodi and Jellyfin libraries"),
+        )
+        .arg(
+            Arg::with_name("db")
+                .long("db")
+                .default_value("phantom.db")
+                .help("local database for WebDAV properties and locks"),
+        )
+        .arg(
+            Arg::with_name("fake-locks")
+                .long("fake-locks")
+                .help("pretend every LOCK succeeds, for macOS Finder"),
+        )
+        .arg(
+            Arg::with_name("persist-locks")
+                .long("persist-locks")
+                .help("keep WebDAV locks in the database across restarts"),
+        )
+        .arg(
+            Arg::with_name("ftp")
+                .long("ftp")
+                .takes_value(true)
+                .help("also serve over FTP on this port, in passive mode, for older devices"),
+        )
+        .arg(
+            Arg::with_name("dlna")
+                .long("dlna")
+                .help("also serve as a DLNA media server, for smart TVs"),
+        )
+        .arg(
+            Arg::with_name("dlna-name")
+                .long("dlna-name")
+                .default_value("phantom")
+                .help("name of the DLNA media server"),
+        )
+        .subcommand(
+            SubCommand::with_name("mount")
+                .about("mount through FUSE instead of serving WebDAV")
+                .arg(Arg::with_name("dir").required(true).help("mount point")),
         )
         .get_matches();
 
-    let fs: Box<dyn DavFileSystem> = match matches.value_of("type").unwrap() {
-        "oof" => OofFS::new(),
-        _ => JellyfinFS::new(),
+    let port = matches.value_of("port").unwrap().parse().unwrap();
+    let ip: IpAddr = matches.value_of("bind").unwrap().parse().unwrap();
+    let addr = SocketAddr::new(ip, port);
+    // where players reach us, for the stream urls in playlists.
+    let host = match ip {
+        ip if ip.is_unspecified() => dlna::local_ip()
+            .map(IpAddr::V4)
+            .unwrap_or_else(|_| [127, 0, 0, 1].into()),
+        ip => ip,
+    };
+
+    let cache_size: usize = matches.value_of("cache-size").unwrap().parse().unwrap();
+    let options = Options {
+        cache_size: cache_size * 1024 * 1024,
+        max_nodes: matches.value_of("max-nodes").unwrap().parse().unwrap(),
+        sanitize: matches.value_of("sanitize").unwrap().parse().unwrap(),
+        playlist: matches.value_of("playlist").unwrap().parse().unwrap(),
+        stream_base: format!("http://{}", SocketAddr::new(host, port)),
+    };
+
+    let db = sled::open(matches.value_of("db").unwrap()).expect("failed to open database");
+    let props = PropStore::new(db.clone());
+
+    let roots = matches
+        .values_of("root")
+        .map(|roots| roots.map(PathBuf::from).collect::<Vec<_>>());
+    let fs: Arc<dyn PhantomFs> = match matches.value_of("type").unwrap() {
+        "oof" => hybrid(*OofFS::new(&options, props), roots),
+        "local" => {
+            let roots = roots.expect("the local FS type needs at least one --root");
+            Arc::new(*LocalFS::new(&options, props, roots))
+        }
+        "webdav" => hybrid(*WebDavFS::new(&options, props), roots),
+        "s3" => hybrid(*S3FS::new(&options, props), roots),
+        "plex" => hybrid(*PlexFS::new(&options, props), roots),
+        "emby" => hybrid(*JellyfinFS::new(&options, props, Flavor::Emby), roots),
+        _ => hybrid(*JellyfinFS::new(&options, props, Flavor::Jellyfin), roots),
+    };
+
+    if let Some(mount) = matches.subcommand_matches("mount") {
+        let dir = mount.value_of("dir").unwrap();
+        tracing::info!("Mounting on {}", dir);
+        if let Err(e) = fuse::mount(fs, dir).await {
+            eprintln!("mount error: {}", e);
+        }
+        return;
+    }
+
+    let dlna = if matches.is_present("dlna") {
+        let ip = match ip {
+            IpAddr::V4(ip) if !ip.is_unspecified() => ip,
+            _ => dlna::local_ip().expect("failed to find the local address"),
+        };
+        if ip.is_loopback() {
+            tracing::warn!("DLNA renderers can't reach {}, see --bind", ip);
+        }
+        let name = matches.value_of("dlna-name").unwrap();
+        let dlna = dlna::Dlna::new(fs.clone(), &db, SocketAddrV4::new(ip, port), name);
+        dlna.announce();
+        Some(Arc::new(dlna))
+    } else {
+        None
+    };
+
+    if let Some(ftp_port) = matches.value_of("ftp") {
+        let addr = SocketAddr::new(ip, ftp_port.parse().unwrap());
+        let fs = fs.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ftp::serve(fs, addr).await {
+                eprintln!("ftp server error: {}", e);
+            }
+        });
+    }
+
+    let ls: Box<dyn DavLockSystem> = if matches.is_present("fake-locks") {
+        FakeLs::new()
+    } else if matches.is_present("persist-locks") {
+        LockSystem::new(Some(db.open_tree("locks").expect("failed to open lock store")))
+    } else {
+        LockSystem::new(None)
     };
 
     let dav_server = DavHandler::builder()
-        .filesystem(fs)
-        .locksystem(FakeLs::new())
+        .filesystem(fs.box_clone())
+        .locksystem(ls)
         .build_handler();
 
     let make_service = hyper::service::make_service_fn(move |_| {
         let dav_server = dav_server.clone();
+        let fs = fs.clone();
+        let dlna = dlna.clone();
         async move {
-            let func = move |req| {
+            let func = move |req: hyper::Request<hyper::Body>| {
                 let dav_server = dav_server.clone();
-                async move { Ok::<_, Infallible>(dav_server.handle(req).await) }
+                let fs = fs.clone();
+                let dlna = dlna.clone();
+                async move {
+                    if stream::is_stream(&req) {
+                        return Ok(stream::serve(&*fs, &req).await);
+                    }
+                    if let Some(dlna) = dlna.filter(|_| dlna::is_dlna(&req)) {
+                        return Ok(dlna.handle(req).await.map(hyper::Body::wrap_stream));
+                    }
+                    if let Some(res) = index::serve(&*fs, &req).await {
+                        return Ok(res.map(hyper::Body::wrap_stream));
+                    }
+                    let path = match *req.method() {
+                        http::Method::GET | http::Method::HEAD => DavPath::from_uri(req.uri()).ok(),
+                        _ => None,
+                    };
+                    // the handler only knows content types by extension.
+                    let mut res = dav_server.handle(req).await;
+                    if let Some(path) = path {
+                        mime::fix_response(&*fs, &path, &mut res).await;
+                    }
+                    Ok::<_, Infallible>(res.map(hyper::Body::wrap_stream))
+                }
             };
             Ok::<_, Infallible>(hyper::service::service_fn(func))
         }
     });
 
-    let port = matches.value_of("port").unwrap().parse().unwrap();
-    let addr = ([127, 0, 0, 1], port).into();
     tracing::info!("Serving on {}", addr);
     let _ = hyper::Server::bind(&addr)
         .serve(make_service)
         .await
         .map_err(|e| eprintln!("server error: {}", e));
 }
+
+// serve `fs` with the local `roots`, if any, merged into its root.
+fn hybrid<B: Backend>(fs: TreeFs<B>, roots: Option<Vec<PathBuf>>) -> Arc<dyn PhantomFs> {
+    match roots {
+        Some(roots) => Arc::new(fs.map_backend(|backend| Hybrid::new(backend, roots))),
+        None => Arc::new(fs),
+    }
+}
@@ -0,0 +1,76 @@
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::FsFuture;
+
+// types that mime_guess gets wrong, or doesn't know, for media files.
+const OVERRIDES: &[(&str, &str)] = &[
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("ts", "video/mp2t"),
+    ("m2ts", "video/mp2t"),
+    ("ass", "text/x-ssa"),
+    ("ssa", "text/x-ssa"),
+    ("nfo", "text/xml"),
+    ("strm", "text/plain"),
+];
+
+/// Content type of files we know nothing about.
+pub const DEFAULT: &str = "application/octet-stream";
+
+/// Filesystems that know the content type of their files.
+pub trait ContentTypes: Send + Sync {
+    /// Content type of the file at `path`.
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String>;
+}
+
+/// Content type of a file called `name`. The extension decides for the
+/// types we serve ourselves (playlists, subtitles); otherwise `hint`,
+/// a file extension or container name reported by the backend, wins.
+pub fn content_type(name: &str, hint: Option<&str>) -> Option<&'static str> {
+    let ext = name.rsplit_once('.').map(|(_, ext)| ext);
+    if let Some(t) = ext.and_then(override_type) {
+        return Some(t);
+    }
+    hint.into_iter()
+        .chain(ext)
+        .find_map(|ext| override_type(ext).or_else(|| mime_guess::from_ext(ext).first_raw()))
+}
+
+/// Fallback for backends that only know the kind of media, like
+/// Jellyfin's `MediaType`.
+pub fn media_type(kind: &str) -> Option<&'static str> {
+    match kind {
+        "Video" => Some("video/mp4"),
+        "Audio" => Some("audio/mpeg"),
+        "Photo" => Some("image/jpeg"),
+        "Book" => Some("application/epub+zip"),
+        _ => None,
+    }
+}
+
+/// The handler only looks at the extension of the request path, so
+/// replace the Content-Type of successful GET/HEAD responses with the
+/// one the filesystem knows. Multipart range responses are left alone.
+pub async fn fix_response<B>(fs: &dyn ContentTypes, path: &DavPath, res: &mut Response<B>) {
+    if res.status() != StatusCode::OK && res.status() != StatusCode::PARTIAL_CONTENT {
+        return;
+    }
+    let multipart = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|t| t.as_bytes().starts_with(b"multipart/"))
+        .unwrap_or(false);
+    if multipart {
+        return;
+    }
+    if let Ok(t) = fs.content_type(path).await {
+        if let Ok(value) = HeaderValue::from_str(&t) {
+            res.headers_mut().insert(CONTENT_TYPE, value);
+        }
+    }
+}
+
+fn override_type(ext: &str) -> Option<&'static str> {
+    let ext = ext.to_ascii_lowercase();
+    OVERRIDES.iter().find(|(e, _)| *e == ext).map(|(_, t)| *t)
+}
@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// How names from the backend are made safe for use in a path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sanitize {
+    /// Only replace `/` and control characters.
+    Minimal,
+    /// Also replace the characters Windows doesn't allow in file names,
+    /// and strip trailing dots and spaces. Names then compare case
+    /// insensitively when looking for duplicates.
+    Windows,
+}
+
+impl FromStr for Sanitize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sanitize, String> {
+        match s {
+            "minimal" => Ok(Sanitize::Minimal),
+            "windows" => Ok(Sanitize::Windows),
+            _ => Err(format!("unknown sanitize mode: {}", s)),
+        }
+    }
+}
+
+/// Replace the characters in `name` that can't be used in a path segment.
+pub fn sanitize(name: &str, mode: Sanitize) -> String {
+    let mut name = name
+        .chars()
+        .map(|c| match c {
+            '/' => '_',
+            c if c.is_control() => '_',
+            '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' if mode == Sanitize::Windows => '_',
+            c => c,
+        })
+        .collect::<String>();
+    if mode == Sanitize::Windows {
+        let len = name.trim_end_matches(['.', ' ']).len();
+        name.truncate(len);
+    }
+    match name.as_str() {
+        "" => "_".to_owned(),
+        "." | ".." => format!("_{}", name),
+        _ => name,
+    }
+}
+
+/// Sanitize the names of a directory listing and make them unique. The
+/// first entry with a name keeps it, later ones get a counter before the
+/// extension, like `name (2).ext`. Callers sort the entries by a stable
+/// key first, so the same listing always yields the same names.
+pub fn unique<'a>(names: impl Iterator<Item = &'a str>, mode: Sanitize) -> Vec<String> {
+    let key = |name: &str| match mode {
+        Sanitize::Minimal => name.to_owned(),
+        Sanitize::Windows => name.to_lowercase(),
+    };
+    let names = names.map(|n| sanitize(n, mode)).collect::<Vec<_>>();
+
+    // names as they come from the backend win over generated ones.
+    let mut taken = HashSet::new();
+    let dups = names
+        .iter()
+        .map(|name| !taken.insert(key(name)))
+        .collect::<Vec<_>>();
+
+    names
+        .into_iter()
+        .zip(dups)
+        .map(|(name, dup)| {
+            if !dup {
+                return name;
+            }
+            let (stem, ext) = match name.rfind('.') {
+                Some(i) if i > 0 => name.split_at(i),
+                _ => (name.as_str(), ""),
+            };
+            (2..)
+                .map(|n| format!("{} ({}){}", stem, n, ext))
+                .find(|candidate| taken.insert(key(candidate)))
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Like `unique`, for listings where some entries go with another one,
+/// like the subtitles of a video. These have the index of that entry and
+/// the rest of their name after its stem, and are named after it once it
+/// is unique, so `Pilot.en.srt` becomes `Pilot (2).en.srt` next to
+/// `Pilot (2).m3u8` instead of `Pilot.en (2).srt`.
+pub fn unique_following(names: &[(&str, Option<(usize, &str)>)], mode: Sanitize) -> Vec<String> {
+    // entries that follow one that follows another keep their own name.
+    let (lead, follow): (Vec<usize>, Vec<usize>) =
+        (0..names.len()).partition(|&i| names[i].1.is_none_or(|(to, _)| names[to].1.is_some()));
+
+    let mut result = vec![String::new(); names.len()];
+    for (&i, name) in lead
+        .iter()
+        .zip(unique(lead.iter().map(|&i| names[i].0), mode))
+    {
+        result[i] = name;
+    }
+    let derived = follow
+        .iter()
+        .map(|&i| {
+            let (to, rest) = names[i].1.unwrap();
+            let name = &result[to];
+            let stem = match name.rfind('.') {
+                Some(i) if i > 0 => &name[..i],
+                _ => name.as_str(),
+            };
+            format!("{}{}", stem, rest)
+        })
+        .collect::<Vec<_>>();
+
+    // the leads are unique already and keep their names, only the names
+    // of the others can still clash.
+    let all = lead
+        .iter()
+        .map(|&i| result[i].as_str())
+        .chain(derived.iter().map(String::as_str));
+    let all = unique(all, mode);
+    for (&i, name) in lead.iter().chain(&follow).zip(all) {
+        result[i] = name;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_numbers_duplicates() {
+        let names = ["a.txt", "a.txt", "a (2).txt", "b", "b"];
+        assert_eq!(
+            unique(names.iter().copied(), Sanitize::Minimal),
+            vec!["a.txt", "a (3).txt", "a (2).txt", "b", "b (2)"]
+        );
+    }
+
+    #[test]
+    fn unique_ignores_case_on_windows() {
+        let names = ["Movie.mkv", "movie.mkv", "a:b"];
+        assert_eq!(
+            unique(names.iter().copied(), Sanitize::Windows),
+            vec!["Movie.mkv", "movie (2).mkv", "a_b"]
+        );
+        assert_eq!(
+            unique(names.iter().copied(), Sanitize::Minimal),
+            vec!["Movie.mkv", "movie.mkv", "a:b"]
+        );
+    }
+
+    #[test]
+    fn companions_follow_their_video() {
+        let names = [
+            ("Pilot.m3u8", None),
+            ("Pilot.m3u8", None),
+            ("Pilot.en.srt", Some((0, ".en.srt"))),
+            ("Pilot.en.srt", Some((1, ".en.srt"))),
+            ("Pilot-poster.jpg", Some((1, "-poster.jpg"))),
+        ];
+        assert_eq!(
+            unique_following(&names, Sanitize::Minimal),
+            vec![
+                "Pilot.m3u8",
+                "Pilot (2).m3u8",
+                "Pilot.en.srt",
+                "Pilot (2).en.srt",
+                "Pilot (2)-poster.jpg"
+            ]
+        );
+    }
+}
@@ -1,18 +1,30 @@
+use crate::mime;
 use crate::oof::oof_file::OofFile;
-use reqwest::header::{HeaderMap, COOKIE, USER_AGENT};
-use reqwest::Client;
+use crate::options::Playlist;
+use crate::quota::Quota;
+use crate::stream::{self, Media};
+use crate::treefs::{self, Chunks, Entry};
+use bytes::Bytes;
+use futures::StreamExt;
+use reqwest::header::{HeaderMap, COOKIE, RANGE, USER_AGENT};
+use reqwest::{Client, RequestBuilder, StatusCode};
 use serde_json::Value::Array;
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::ops::Add;
 use std::time::{Duration, UNIX_EPOCH};
+use webdav_handler::fs::{FsError, FsResult};
 
 #[derive(Debug, Clone)]
 pub struct ClientOof {
     client: Client,
+    playlist: Playlist,
+    // our own url, see `stream::url`.
+    stream_base: String,
 }
 
 impl ClientOof {
-    pub fn new() -> ClientOof {
+    pub fn new(playlist: Playlist, stream_base: String) -> ClientOof {
         let cookie = fs::read_to_string("115.cookie").expect("file `115.cookie` does not exits");
         let mut headers = HeaderMap::new();
         headers.insert(USER_AGENT, "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_16_0) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/83.0.4103.61 Safari/537.36 115Browser/24.1.0.13".parse().unwrap());
@@ -20,30 +32,29 @@ impl ClientOof {
 
         let client = Client::builder().default_headers(headers).build().unwrap();
 
-        ClientOof { client }
+        ClientOof {
+            client,
+            playlist,
+            stream_base,
+        }
     }
 
-    pub async fn opendir(&self, cid: u64) -> Vec<OofFile> {
-        let cid = match cid {
-            1 => 0,
-            _ => cid,
-        };
-
+    /// List the folder `cid`, 0 for the root.
+    pub async fn opendir(&self, cid: u64) -> FsResult<Vec<Entry<OofFile>>> {
         let url = format!("https://webapi.115.com/files?aid=1&cid={}&o=user_ptime&asc=0&offset=0&show_dir=1&limit=1000", cid);
-        let res: serde_json::Value = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .unwrap()
-            .json()
-            .await
-            .unwrap();
-        if let Some(error) = res.get("error") {
-            tracing::error!("opendir failed! {}", error.as_str().unwrap());
+        let res: serde_json::Value = self.get(url).await?.json().await.map_err(|e| {
+            tracing::error!("opendir failed! {}", e);
+            FsError::GeneralFailure
+        })?;
+        if res["state"].as_bool() == Some(false) {
+            tracing::error!("opendir failed! {}", res["error"]);
+            return Err(FsError::GeneralFailure);
         }
 
         let mut files = Vec::new();
+        // names of the videos and their possible companion files.
+        let mut videos = Vec::new();
+        let mut companions = Vec::new();
         match &res["data"] {
             Array(data) => {
                 for d in data {
@@ -55,78 +66,294 @@ impl ClientOof {
                         let ino = fid.as_str().unwrap().parse().unwrap();
                         if let Some(_) = d.get("play_long") {
                             let pickcode = d["pc"].as_str().unwrap();
-                            let file_content = self.download(pickcode).await;
-                            let size = file_content.len();
-                            let data = Some(file_content);
-                            name = format!("{}.m3u8", name);
-
-                            OofFile {
+                            let key = OofFile {
                                 id: ino,
-                                name,
-                                size,
                                 pickcode: pickcode.to_owned(),
+                                playlist: true,
+                            };
+                            videos.push((name.clone(), key.clone()));
+                            let media = Media {
+                                id: pickcode.to_owned(),
+                                content_type: mime::content_type(&name, d["ico"].as_str())
+                                    .or_else(|| mime::media_type("Video"))
+                                    .unwrap_or(mime::DEFAULT)
+                                    .to_owned(),
+                            };
+                            let file_content = self.playlist(pickcode);
+                            name = format!("{}.{}", name, self.playlist.extension());
+                            let content_type = mime::content_type(&name, d["ico"].as_str())
+                                .unwrap_or(mime::DEFAULT)
+                                .to_string();
+
+                            Entry {
+                                key,
+                                name,
+                                size: Some(file_content.len() as u64),
+                                mtime: time,
                                 ctime: time,
-                                is_file: true,
-                                data,
+                                is_dir: false,
+                                etag: content_etag(&file_content),
+                                content_type,
+                                follows: None,
+                                media: Some(media),
                             }
                         } else {
+                            if is_companion(&name) {
+                                companions.push(d);
+                            }
                             continue;
                         }
                     } else {
                         let ino = d["cid"].as_str().unwrap().parse().unwrap();
-                        OofFile {
-                            id: ino,
+                        Entry {
+                            key: OofFile::folder(ino),
                             name,
-                            size: 0,
-                            pickcode: "".to_owned(),
+                            size: Some(0),
+                            mtime: time,
                             ctime: time,
-                            is_file: false,
-                            data: None,
+                            is_dir: true,
+                            etag: format!("{:x}-{:x}", ino, ut),
+                            content_type: "httpd/unix-directory".to_owned(),
+                            follows: None,
+                            media: None,
                         }
                     };
 
                     tracing::info!(
                         "load file info: {} -> {} (size: {})",
-                        file_info.id,
+                        file_info.key.id,
                         file_info.name,
-                        file_info.size
+                        file_info.size.unwrap_or(0)
                     );
                     files.push(file_info);
                 }
             }
             _ => {}
         }
-        files
+
+        // show companions next to the video they belong to, renamed to
+        // match its playlist, like `X.srt` -> `X.mp4.srt` for `X.mp4.m3u8`,
+        // the others under their own name.
+        for d in companions {
+            let name = d["n"].as_str().unwrap();
+            let video = videos
+                .iter()
+                .filter_map(|(v, key)| {
+                    let stem = v.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(v);
+                    let rest = name.strip_prefix(stem)?;
+                    rest.starts_with('.').then_some((stem.len(), v, key, rest))
+                })
+                .max();
+            let (name, follows) = match video {
+                Some((_, v, key, rest)) => {
+                    (format!("{}{}", v, rest), Some((key.clone(), rest.to_owned())))
+                }
+                // no video to go with, it's listed as it is.
+                None => (name.to_owned(), None),
+            };
+            let ino = d["fid"].as_str().unwrap().parse().unwrap();
+            let ut: u64 = d["te"].as_str().unwrap().parse().unwrap();
+            let time = UNIX_EPOCH.add(Duration::from_secs(ut));
+            let content_type = mime::content_type(&name, d["ico"].as_str())
+                .unwrap_or(mime::DEFAULT)
+                .to_string();
+            let file_info = Entry {
+                key: OofFile {
+                    id: ino,
+                    pickcode: d["pc"].as_str().unwrap().to_owned(),
+                    playlist: false,
+                },
+                size: Some(d["s"].as_u64().unwrap_or(0)),
+                mtime: time,
+                ctime: time,
+                is_dir: false,
+                etag: etag(d, ino, ut),
+                content_type,
+                name,
+                follows,
+                media: None,
+            };
+            tracing::info!("load companion: {} -> {}", file_info.key.id, file_info.name);
+            files.push(file_info);
+        }
+        Ok(files)
     }
 
-    async fn download(&self, pickcode: &str) -> Vec<u8> {
-        let url = format!("http://115.com/api/video/m3u8/{}.m3u8", pickcode);
+    /// Read `len` bytes from `start` of the file `pickcode`.
+    pub async fn read(&self, pickcode: &str, start: u64, len: usize) -> FsResult<Bytes> {
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+        let url = self.file_url(pickcode).await?;
+        let range = format!("bytes={}-{}", start, start + len as u64 - 1);
         let res = self
             .client
-            .get(url)
+            .get(&url)
+            .header(RANGE, range)
             .send()
             .await
-            .unwrap()
-            .text()
+            .map_err(|e| {
+                tracing::error!("read failed! {}", e);
+                FsError::GeneralFailure
+            })?;
+        let status = res.status();
+        if status == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(Bytes::new());
+        }
+        let data = res
+            .error_for_status()
+            .map_err(|e| {
+                tracing::error!("read failed! {}", e);
+                FsError::GeneralFailure
+            })?
+            .bytes()
+            .await
+            .map_err(|e| {
+                tracing::error!("read failed! {}", e);
+                FsError::GeneralFailure
+            })?;
+        if status != StatusCode::OK {
+            return Ok(data);
+        }
+        // the server ignored the range and sent everything.
+        let start = (start as usize).min(data.len());
+        let end = (start + len).min(data.len());
+        Ok(data.slice(start..end))
+    }
+
+    /// Stream the file `pickcode` from `start` to its end.
+    pub async fn open(&self, pickcode: &str, start: u64) -> FsResult<Chunks> {
+        let url = self.file_url(pickcode).await?;
+        let res = self
+            .client
+            .get(&url)
+            .header(RANGE, format!("bytes={}-", start))
+            .send()
             .await
-            .unwrap();
-
-        // fix m3u8, just keep one video address.
-        let mut t = vec![];
-        for text in res.lines() {
-            t.push(text.trim());
-            if text.starts_with("http") {
-                break;
+            .map_err(|e| {
+                tracing::error!("read failed! {}", e);
+                FsError::GeneralFailure
+            })?;
+        match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(futures::stream::empty().boxed()),
+            // the server ignored the range and sends everything.
+            StatusCode::OK => Ok(treefs::body(res, start)),
+            _ => {
+                let res = res.error_for_status().map_err(|e| {
+                    tracing::error!("read failed! {}", e);
+                    FsError::GeneralFailure
+                })?;
+                Ok(treefs::body(res, 0))
             }
         }
+    }
 
-        let result = t.join("\r\n");
-        result.into_bytes()
+    // the download url of a file.
+    async fn file_url(&self, pickcode: &str) -> FsResult<String> {
+        let url = format!("https://webapi.115.com/files/download?pickcode={}", pickcode);
+        let res: serde_json::Value = self.get(url).await?.json().await.map_err(|e| {
+            tracing::error!("download failed! {}", e);
+            FsError::GeneralFailure
+        })?;
+        match res["file_url"].as_str() {
+            Some(url) if res["state"].as_bool() == Some(true) => Ok(url.to_owned()),
+            _ => {
+                tracing::error!("download failed! {}", res["msg"]);
+                Err(FsError::GeneralFailure)
+            }
+        }
     }
+
+    /// Space used by the account, and its total space.
+    pub async fn quota(&self) -> FsResult<Quota> {
+        let url = "https://webapi.115.com/files/index_info".to_owned();
+        let res: serde_json::Value = self.get(url).await?.json().await.map_err(|e| {
+            tracing::error!("quota failed! {}", e);
+            FsError::GeneralFailure
+        })?;
+        if res["state"].as_bool() == Some(false) {
+            tracing::error!("quota failed! {}", res["error"]);
+            return Err(FsError::GeneralFailure);
+        }
+
+        // sizes are sometimes sent as floats.
+        let size = |v: &serde_json::Value| {
+            let size = &v["size"];
+            size.as_u64().or_else(|| size.as_f64().map(|s| s as u64))
+        };
+        let space = &res["data"]["space_info"];
+        let used = size(&space["all_use"]).ok_or(FsError::GeneralFailure)?;
+        Ok((used, size(&space["all_total"])))
+    }
+
+    /// The playlist or .strm file of the video `pickcode`. Both point at
+    /// our stream url, which signs a new url for every request: signed
+    /// urls expire, and the content has to keep the size it was listed
+    /// with.
+    pub fn playlist(&self, pickcode: &str) -> Vec<u8> {
+        let url = stream::url(&self.stream_base, pickcode);
+        self.playlist.content(&url).into_bytes()
+    }
+
+    /// The request for the stream of the video `pickcode`, with a
+    /// freshly signed url.
+    pub async fn stream(&self, pickcode: &str) -> FsResult<RequestBuilder> {
+        if pickcode.is_empty() || !pickcode.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(FsError::NotFound);
+        }
+        let res = self.m3u8(pickcode).await?;
+        let url = res.lines().map(|l| l.trim()).find(|l| l.starts_with("http"));
+        match url {
+            Some(url) => Ok(self.client.get(url)),
+            None => {
+                tracing::error!("no stream for {}: {}", pickcode, res);
+                Err(FsError::NotFound)
+            }
+        }
+    }
+
+    // the playlist 115 serves for a video.
+    async fn m3u8(&self, pickcode: &str) -> FsResult<String> {
+        let url = format!("http://115.com/api/video/m3u8/{}.m3u8", pickcode);
+        self.get(url).await?.text().await.map_err(|e| {
+            tracing::error!("download failed! {}", e);
+            FsError::GeneralFailure
+        })
+    }
+
+    async fn get(&self, url: String) -> FsResult<reqwest::Response> {
+        let res = self.client.get(&url).send().await.map_err(|e| {
+            tracing::error!("request {} failed! {}", url, e);
+            FsError::GeneralFailure
+        })?;
+        res.error_for_status().map_err(|e| {
+            tracing::error!("request {} failed! {}", url, e);
+            FsError::GeneralFailure
+        })
+    }
+}
+
+// subtitles and other files players look for next to a video.
+fn is_companion(name: &str) -> bool {
+    const EXTS: &[&str] = &["srt", "ass", "ssa", "vtt", "sub", "idx", "sup", "smi"];
+    match name.rsplit_once('.') {
+        Some((_, ext)) => EXTS.contains(&ext.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// The ETag of a playlist, which is not the video the sha of 115 is of.
+pub fn content_etag(data: &[u8]) -> String {
+    Sha256::digest(data)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
 }
 
-impl Default for ClientOof {
-    fn default() -> Self {
-        ClientOof::new()
+// the content hash of a file, if 115 has one.
+fn etag(d: &serde_json::Value, ino: u64, ut: u64) -> String {
+    match d["sha"].as_str() {
+        Some(sha) => sha.to_lowercase(),
+        None => format!("{:x}-{:x}", ino, ut),
     }
 }
@@ -1,11 +1,31 @@
-use std::time::SystemTime;
+use std::borrow::Cow;
 
+use crate::treefs::Key;
+
+/// A file or folder on 115, as the key of its node.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct OofFile {
+    /// The fid of a file, or the cid of a folder.
     pub id: u64,
+    /// Empty for folders.
     pub pickcode: String,
-    pub name: String,
-    pub size: usize,
-    pub ctime: SystemTime,
-    pub is_file: bool,
-    pub data: Option<Vec<u8>>,
+    /// Served as the playlist or .strm file of a video, not as the file
+    /// itself.
+    pub playlist: bool,
+}
+
+impl OofFile {
+    pub fn folder(cid: u64) -> OofFile {
+        OofFile {
+            id: cid,
+            pickcode: String::new(),
+            playlist: false,
+        }
+    }
+}
+
+impl Key for OofFile {
+    fn as_text(&self) -> Cow<'_, str> {
+        Cow::Owned(self.id.to_string())
+    }
 }
@@ -0,0 +1,58 @@
+use std::str::FromStr;
+
+use crate::names::Sanitize;
+
+/// Runtime options shared by the filesystems, filled in from the command line.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Maximum number of bytes of file content kept in memory.
+    pub cache_size: usize,
+    /// Number of tree nodes above which directory listings get evicted.
+    pub max_nodes: usize,
+    /// How names from the backend are made safe for paths.
+    pub sanitize: Sanitize,
+    /// What videos of backends that only have a stream url are served as.
+    pub playlist: Playlist,
+    /// Our own url, like `http://192.168.1.2:9867`, which playlists
+    /// point at, see `stream::Streams`.
+    pub stream_base: String,
+}
+
+/// The files standing in for a video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Playlist {
+    /// An m3u8 playlist, which players open like the video.
+    M3u8,
+    /// A `.strm` file holding only the stream url, which media centers
+    /// like Kodi and Jellyfin import into their libraries.
+    Strm,
+}
+
+impl Playlist {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Playlist::M3u8 => "m3u8",
+            Playlist::Strm => "strm",
+        }
+    }
+
+    /// The content of the file for a video at `url`.
+    pub fn content(&self, url: &str) -> String {
+        match self {
+            Playlist::M3u8 => format!("#EXTM3U\r\n#EXT-X-VERSION:7\r\n{}", url),
+            Playlist::Strm => format!("{}\n", url),
+        }
+    }
+}
+
+impl FromStr for Playlist {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Playlist, String> {
+        match s {
+            "m3u8" => Ok(Playlist::M3u8),
+            "strm" => Ok(Playlist::Strm),
+            _ => Err(format!("unknown playlist format: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use http::header::{ACCEPT, RANGE};
+use http::StatusCode;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value::{self, Array};
+use webdav_handler::fs::{FsError, FsResult};
+
+use crate::mime;
+use crate::options::Playlist;
+use crate::plex::config::Config;
+use crate::stream::{self, Media};
+use crate::treefs::{self, Chunks, Entry};
+
+/// The library sections, listed at the root.
+pub const SECTIONS: &str = "/library/sections";
+
+#[derive(Debug, Clone)]
+pub struct PlexClient {
+    client: Client,
+    config: Config,
+    // what media parts are served as, None for the parts themselves.
+    playlist: Option<Playlist>,
+    // our own url, see `stream::url`.
+    stream_base: String,
+}
+
+impl PlexClient {
+    pub fn new(mut config: Config, playlist: Option<Playlist>, stream_base: String) -> PlexClient {
+        config.server = config.server.trim_end_matches('/').to_owned();
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        if let Ok(token) = HeaderValue::from_str(&config.token) {
+            headers.insert("X-Plex-Token", token);
+        }
+        let client = Client::builder().default_headers(headers).build().unwrap();
+        PlexClient {
+            client,
+            config,
+            playlist,
+            stream_base,
+        }
+    }
+
+    /// List the directory at `path`: the sections, the items of a
+    /// section, or the children of an item, like the seasons of a show.
+    pub async fn opendir(&self, path: &str) -> FsResult<Vec<Entry<String>>> {
+        let res = self.get_json(path).await?;
+        let container = &res["MediaContainer"];
+
+        let mut files = Vec::new();
+        if let Array(dirs) = &container["Directory"] {
+            files.extend(dirs.iter().filter_map(|d| directory(d, path)));
+        }
+        if let Array(items) = &container["Metadata"] {
+            for item in items {
+                // only playable items have media.
+                if item["Media"].is_array() {
+                    files.extend(self.parts(item));
+                } else {
+                    files.extend(directory(item, path));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    // the files of the media parts of `item`. Items with several versions
+    // or parts get a file for each.
+    fn parts(&self, item: &Value) -> Vec<Entry<String>> {
+        let title = item["title"].as_str().unwrap_or("");
+        let mtime = mtime(item);
+        let mut files = Vec::new();
+        for media in item["Media"].as_array().into_iter().flatten() {
+            for part in media["Part"].as_array().into_iter().flatten() {
+                let key = match part["key"].as_str() {
+                    Some(key) => key.to_owned(),
+                    None => continue,
+                };
+                let container = part["container"]
+                    .as_str()
+                    .or_else(|| media["container"].as_str());
+                // named like the file on the server.
+                let base = part["file"]
+                    .as_str()
+                    .and_then(|f| f.rsplit(['/', '\\']).next())
+                    .filter(|f| !f.is_empty())
+                    .map(|f| f.to_owned())
+                    .unwrap_or_else(|| match container {
+                        Some(container) => format!("{}.{}", title, container),
+                        None => title.to_owned(),
+                    });
+                let size = part["size"].as_u64().unwrap_or(0);
+                let etag = format!("{}-{}", part["id"], item["updatedAt"]);
+
+                let (name, size, media) = match self.playlist {
+                    Some(playlist) => {
+                        let name = format!("{}.{}", base, playlist.extension());
+                        let media = Media {
+                            id: key.clone(),
+                            content_type: mime::content_type(&base, container)
+                                .or_else(|| item["type"].as_str().and_then(media_type))
+                                .unwrap_or(mime::DEFAULT)
+                                .to_owned(),
+                        };
+                        let size = self.text(playlist, &key).len() as u64;
+                        (name, size, Some(media))
+                    }
+                    None => (base, size, None),
+                };
+                let content_type = mime::content_type(&name, container)
+                    .or_else(|| item["type"].as_str().and_then(media_type))
+                    .unwrap_or(mime::DEFAULT)
+                    .to_owned();
+                files.push(Entry {
+                    key,
+                    name,
+                    size: Some(size),
+                    mtime,
+                    ctime: mtime,
+                    is_dir: false,
+                    etag,
+                    content_type,
+                    follows: None,
+                    media,
+                });
+            }
+        }
+        files
+    }
+
+    /// Read `len` bytes from `start` of the media part `key`, or of its
+    /// playlist.
+    pub async fn read(&self, key: &str, start: u64, len: usize) -> FsResult<Bytes> {
+        if let Some(playlist) = self.playlist {
+            let text = self.text(playlist, key);
+            let start = (start as usize).min(text.len());
+            let end = (start + len).min(text.len());
+            return Ok(Bytes::copy_from_slice(&text.as_bytes()[start..end]));
+        }
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+        let range = format!("bytes={}-{}", start, start + len as u64 - 1);
+        let url = format!("{}{}", self.config.server, key);
+        let res = self
+            .client
+            .get(url)
+            .header(RANGE, range)
+            .send()
+            .await
+            .map_err(error)?;
+        match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Bytes::new()),
+            // the server ignored the range and sent everything.
+            StatusCode::OK => {
+                let data = res.bytes().await.map_err(error)?;
+                let start = (start as usize).min(data.len());
+                let end = (start + len).min(data.len());
+                Ok(data.slice(start..end))
+            }
+            _ => res
+                .error_for_status()
+                .map_err(error)?
+                .bytes()
+                .await
+                .map_err(error),
+        }
+    }
+
+    /// Stream the media part `key` from `start` to its end, None when
+    /// parts are served as playlists.
+    pub async fn open(&self, key: &str, start: u64) -> FsResult<Option<Chunks>> {
+        if self.playlist.is_some() {
+            return Ok(None);
+        }
+        let url = format!("{}{}", self.config.server, key);
+        let res = self
+            .client
+            .get(url)
+            .header(RANGE, format!("bytes={}-", start))
+            .send()
+            .await
+            .map_err(error)?;
+        let chunks = match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => futures::stream::empty().boxed(),
+            // the server ignored the range and sends everything.
+            StatusCode::OK => treefs::body(res, start),
+            _ => treefs::body(res.error_for_status().map_err(error)?, 0),
+        };
+        Ok(Some(chunks))
+    }
+
+    /// The request for the stream of the media part `key`, which its
+    /// playlist points at.
+    pub fn stream(&self, key: &str) -> FsResult<RequestBuilder> {
+        // only media parts, the token would allow any other request.
+        if !key.starts_with("/library/parts/") || key.contains("..") {
+            return Err(FsError::NotFound);
+        }
+        Ok(self.client.get(format!("{}{}", self.config.server, key)))
+    }
+
+    // the playlist of the media part `key`.
+    fn text(&self, playlist: Playlist, key: &str) -> String {
+        playlist.content(&stream::url(&self.stream_base, key))
+    }
+
+    async fn get_json(&self, path: &str) -> FsResult<Value> {
+        let url = format!("{}{}", self.config.server, path);
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(error)?;
+        res.json().await.map_err(error)
+    }
+}
+
+// a directory from a <Directory> of the sections, or an item without
+// media, like a show, season, artist or album.
+fn directory(d: &Value, path: &str) -> Option<Entry<String>> {
+    let key = match d["ratingKey"].as_str() {
+        Some(id) => format!("/library/metadata/{}/children", id),
+        None => match d["key"].as_str()? {
+            key if key.starts_with('/') => key.to_owned(),
+            // sections are keyed by their number.
+            key => format!("{}/{}/all", path, key),
+        },
+    };
+    let mut name = d["title"].as_str()?.to_owned();
+    if let (Some("show"), Some(year)) = (d["type"].as_str(), d["year"].as_u64()) {
+        name = format!("{} ({})", name, year);
+    }
+    let mtime = mtime(d);
+    Some(Entry {
+        etag: format!("{}-{}", key, d["updatedAt"]),
+        key,
+        name,
+        size: Some(0),
+        mtime,
+        ctime: mtime,
+        is_dir: true,
+        content_type: "httpd/unix-directory".to_owned(),
+        follows: None,
+        media: None,
+    })
+}
+
+fn mtime(item: &Value) -> SystemTime {
+    item["updatedAt"]
+        .as_u64()
+        .or_else(|| item["addedAt"].as_u64())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+// fallback for the plex item types, see `mime::media_type`.
+fn media_type(kind: &str) -> Option<&'static str> {
+    match kind {
+        "movie" | "episode" | "clip" => mime::media_type("Video"),
+        "track" => mime::media_type("Audio"),
+        "photo" => mime::media_type("Photo"),
+        _ => None,
+    }
+}
+
+fn error(e: reqwest::Error) -> FsError {
+    tracing::error!("request failed! status: {:?}", e.status());
+    match e.status() {
+        Some(StatusCode::NOT_FOUND) => FsError::NotFound,
+        Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN) => FsError::Forbidden,
+        _ => FsError::GeneralFailure,
+    }
+}
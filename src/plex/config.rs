@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Config {
+    /// The server, like `http://127.0.0.1:32400`.
+    pub server: String,
+    /// The `X-Plex-Token` used for all requests.
+    pub token: String,
+    /// How media parts are served: `m3u8` playlists or `strm` files
+    /// pointing at our stream url, or `file` for the media files
+    /// themselves. Defaults to `--playlist`.
+    #[serde(default)]
+    pub output: String,
+}
@@ -0,0 +1,73 @@
+use std::fs;
+
+use bytes::Bytes;
+use futures::{future, future::FutureExt};
+use reqwest::RequestBuilder;
+use webdav_handler::fs::FsFuture;
+
+use crate::options::Options;
+use crate::plex::client::{PlexClient, SECTIONS};
+use crate::plex::config::Config;
+use crate::props::PropStore;
+use crate::treefs::{Backend, Chunks, Entry, TreeFs};
+
+/// The libraries of a Plex Media Server, read only. Sections are the
+/// top level directories, media parts are served as playlists or as
+/// the files themselves, see `Config::output`.
+pub type PlexFS = TreeFs<PlexClient>;
+
+impl PlexFS {
+    /// Create a new "PlexFS" filesystem, configured by `plex.json`.
+    pub fn new(options: &Options, props: PropStore) -> Box<PlexFS> {
+        let demo_config = serde_json::ser::to_string(&Config::default()).unwrap();
+        let msg = format!(
+            "plex.json does not exists, you should create with the content like:\r\n{}",
+            demo_config
+        );
+        let config_str = fs::read_to_string("plex.json").expect(msg.as_str());
+        let config = serde_json::de::from_str::<Config>(config_str.as_str()).unwrap();
+        let playlist = match config.output.as_str() {
+            "" => Some(options.playlist),
+            "file" => None,
+            output => match output.parse() {
+                Ok(playlist) => Some(playlist),
+                Err(_) => panic!(
+                    "invalid output {:?} in plex.json, use file, m3u8 or strm",
+                    output
+                ),
+            },
+        };
+        let client = PlexClient::new(config, playlist, options.stream_base.clone());
+        Box::new(TreeFs::with_backend(client, options, props))
+    }
+}
+
+impl Backend for PlexClient {
+    // directories are keyed by the path listing them, files by the key
+    // of their media part.
+    type Key = String;
+
+    fn root(&self) -> String {
+        SECTIONS.to_owned()
+    }
+
+    fn opendir<'a>(&'a self, path: &'a String) -> FsFuture<'a, Vec<Entry<String>>> {
+        PlexClient::opendir(self, path).boxed()
+    }
+
+    fn read<'a>(&'a self, key: &'a String, start: u64, len: usize) -> FsFuture<'a, Bytes> {
+        PlexClient::read(self, key, start, len).boxed()
+    }
+
+    fn open<'a>(&'a self, key: &'a String, start: u64) -> FsFuture<'a, Option<Chunks>> {
+        PlexClient::open(self, key, start).boxed()
+    }
+
+    fn prop_prefix(&self) -> &'static str {
+        "plex"
+    }
+
+    fn stream<'a>(&'a self, key: &'a str) -> FsFuture<'a, RequestBuilder> {
+        future::ready(PlexClient::stream(self, key)).boxed()
+    }
+}
@@ -0,0 +1,153 @@
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use webdav_handler::fs::{DavProp, FsError, FsResult};
+
+/// Dead properties set through PROPPATCH, persisted in a local sled
+/// database. Entries are keyed by backend item id (e.g. `oof:1234`),
+/// so they survive restarts and evictions from the tree.
+#[derive(Debug, Clone)]
+pub struct PropStore {
+    db: sled::Db,
+    // serializes read-modify-write cycles of patch().
+    write: Arc<Mutex<()>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProp {
+    name: String,
+    prefix: Option<String>,
+    namespace: Option<String>,
+    xml: Option<Vec<u8>>,
+}
+
+impl PropStore {
+    /// Create a property store on top of the default tree of `db`.
+    pub fn new(db: sled::Db) -> PropStore {
+        PropStore {
+            db,
+            write: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Get the properties of an item, keyed by namespace + name.
+    pub fn get(&self, key: &str) -> FsResult<HashMap<String, DavProp>> {
+        let value = self.db.get(key).map_err(db_error)?;
+        let stored: Vec<StoredProp> = match value {
+            Some(v) => serde_json::from_slice(&v).map_err(|_| FsError::GeneralFailure)?,
+            None => Vec::new(),
+        };
+        let mut props = HashMap::new();
+        for p in stored {
+            let prop = DavProp {
+                name: p.name,
+                prefix: p.prefix,
+                namespace: p.namespace,
+                xml: p.xml,
+            };
+            props.insert(propkey(&prop.namespace, &prop.name), prop);
+        }
+        Ok(props)
+    }
+
+    /// Modify the properties of an item and write them back.
+    pub async fn patch<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut HashMap<String, DavProp>) -> T,
+    ) -> FsResult<T> {
+        let res = self.update(key, f)?;
+        // sled flushes on its own thread pool, off the runtime.
+        self.db.flush_async().await.map_err(db_error)?;
+        Ok(res)
+    }
+
+    /// Apply a PROPPATCH to the properties of an item.
+    pub async fn patch_props(
+        &self,
+        key: &str,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(StatusCode, DavProp)>> {
+        self.patch(key, |props| {
+            let mut res = Vec::new();
+            for (set, p) in patch {
+                let prop = cloneprop(&p);
+                if set {
+                    props.insert(propkey(&p.namespace, &p.name), p);
+                } else {
+                    // removing a property that does not exist succeeds.
+                    props.remove(&propkey(&p.namespace, &p.name));
+                }
+                res.push((StatusCode::OK, prop));
+            }
+            res
+        })
+        .await
+    }
+
+    /// The properties of an item, without their values unless
+    /// `do_content` is set.
+    pub fn get_props(&self, key: &str, do_content: bool) -> FsResult<Vec<DavProp>> {
+        let mut res = Vec::new();
+        for (_, p) in self.get(key)? {
+            res.push(if do_content { p } else { cloneprop(&p) });
+        }
+        Ok(res)
+    }
+
+    /// The value of one property of an item.
+    pub fn get_prop(&self, key: &str, prop: &DavProp) -> FsResult<Vec<u8>> {
+        let mut props = self.get(key)?;
+        let p = props
+            .remove(&propkey(&prop.namespace, &prop.name))
+            .ok_or(FsError::NotFound)?;
+        p.xml.ok_or(FsError::NotFound)
+    }
+
+    fn update<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut HashMap<String, DavProp>) -> T,
+    ) -> FsResult<T> {
+        let _guard = self.write.lock().unwrap();
+        let mut props = self.get(key)?;
+        let res = f(&mut props);
+        if props.is_empty() {
+            self.db.remove(key).map_err(db_error)?;
+        } else {
+            let stored = props
+                .into_values()
+                .map(|p| StoredProp {
+                    name: p.name,
+                    prefix: p.prefix,
+                    namespace: p.namespace,
+                    xml: p.xml,
+                })
+                .collect::<Vec<_>>();
+            let value = serde_json::to_vec(&stored).map_err(|_| FsError::GeneralFailure)?;
+            self.db.insert(key, value).map_err(db_error)?;
+        }
+        Ok(res)
+    }
+}
+
+// key of a property in the map returned by `PropStore::get`.
+fn propkey(ns: &Option<String>, name: &str) -> String {
+    ns.to_owned().as_ref().unwrap_or(&"".to_string()).clone() + name
+}
+
+// a property without its value.
+fn cloneprop(p: &DavProp) -> DavProp {
+    DavProp {
+        name: p.name.clone(),
+        namespace: p.namespace.clone(),
+        prefix: p.prefix.clone(),
+        xml: None,
+    }
+}
+
+fn db_error(e: sled::Error) -> FsError {
+    tracing::error!("properties database error: {}", e);
+    FsError::GeneralFailure
+}
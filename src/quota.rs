@@ -0,0 +1,53 @@
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use webdav_handler::fs::FsResult;
+
+use crate::flight::SingleFlight;
+
+// failures are kept this long at most, so a broken API isn't hammered
+// but a passing outage doesn't hide the quota for the whole ttl.
+const ERROR_TTL: Duration = Duration::from_secs(30);
+
+/// Bytes used, and the total if known.
+pub type Quota = (u64, Option<u64>);
+
+/// Keeps the quota reported by a backend for a while. File managers ask
+/// for it on every PROPFIND, and fetching it can be expensive.
+#[derive(Debug)]
+pub struct QuotaCache {
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, FsResult<Quota>)>>,
+    flight: SingleFlight<(), FsResult<Quota>>,
+}
+
+impl QuotaCache {
+    pub fn new(ttl: Duration) -> QuotaCache {
+        QuotaCache {
+            ttl,
+            cached: Mutex::new(None),
+            flight: SingleFlight::new(),
+        }
+    }
+
+    /// Return the cached quota, or fetch it with `f` once it expired.
+    pub async fn get<F, Fut>(&self, f: F) -> FsResult<Quota>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = FsResult<Quota>> + Send + 'static,
+    {
+        if let Some((at, res)) = *self.cached.lock().unwrap() {
+            let ttl = match res {
+                Ok(_) => self.ttl,
+                Err(_) => self.ttl.min(ERROR_TTL),
+            };
+            if at.elapsed() < ttl {
+                return res;
+            }
+        }
+        let res = self.flight.run((), f).await;
+        *self.cached.lock().unwrap() = Some((Instant::now(), res));
+        res
+    }
+}
@@ -0,0 +1,251 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use http::header::{AUTHORIZATION, RANGE};
+use http::StatusCode;
+use reqwest::{Client, RequestBuilder, Url};
+use webdav_handler::fs::{FsError, FsResult};
+use xmltree::Element;
+
+use crate::mime;
+use crate::s3::config::Config;
+use crate::s3::sign::{self, UNSIGNED_PAYLOAD};
+use crate::time::Utc;
+use crate::treefs::{self, Chunks, Entry};
+
+#[derive(Debug, Clone)]
+pub struct S3Client {
+    client: Client,
+    config: Config,
+}
+
+impl S3Client {
+    pub fn new(config: Config) -> S3Client {
+        let client = Client::builder().build().unwrap();
+        S3Client { client, config }
+    }
+
+    /// Prefix of the objects that are served.
+    pub fn root(&self) -> String {
+        self.config.prefix.clone()
+    }
+
+    /// List the objects and common prefixes directly below `prefix`,
+    /// following continuation tokens until the listing is complete.
+    pub async fn opendir(&self, prefix: &str) -> FsResult<Vec<Entry<String>>> {
+        let mut files = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("delimiter", "/"), ("prefix", prefix)];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+            let mut url = self.url("")?;
+            url.set_query(Some(&sign::query(&query)));
+            let res = self
+                .request("GET", url)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(error)?;
+            let body = res.bytes().await.map_err(error)?;
+            let res = Element::parse(&body[..]).map_err(|e| {
+                tracing::error!("invalid ListObjectsV2 response for {}: {}", prefix, e);
+                FsError::GeneralFailure
+            })?;
+
+            for e in res.children.iter().filter_map(|c| c.as_element()) {
+                let file = match e.name.as_str() {
+                    "Contents" => object(e, prefix),
+                    "CommonPrefixes" => text(e, "Prefix").and_then(|p| directory(&p, prefix)),
+                    _ => None,
+                };
+                files.extend(file);
+            }
+
+            token = match text(&res, "IsTruncated").as_deref() {
+                Some("true") => text(&res, "NextContinuationToken"),
+                _ => None,
+            };
+            if token.is_none() {
+                return Ok(files);
+            }
+        }
+    }
+
+    /// Read `len` bytes from `start` of the object `key`.
+    pub async fn read(&self, key: &str, start: u64, len: usize) -> FsResult<Bytes> {
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+        let range = format!("bytes={}-{}", start, start + len as u64 - 1);
+        let res = self
+            .request("GET", self.url(key)?)
+            .header(RANGE, range)
+            .send()
+            .await
+            .map_err(error)?;
+        match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Bytes::new()),
+            // some gateways ignore the range and send everything.
+            StatusCode::OK => {
+                let data = res.bytes().await.map_err(error)?;
+                let start = (start as usize).min(data.len());
+                let end = (start + len).min(data.len());
+                Ok(data.slice(start..end))
+            }
+            _ => res
+                .error_for_status()
+                .map_err(error)?
+                .bytes()
+                .await
+                .map_err(error),
+        }
+    }
+
+    /// Stream the object `key` from `start` to its end.
+    pub async fn open(&self, key: &str, start: u64) -> FsResult<Chunks> {
+        let res = self
+            .request("GET", self.url(key)?)
+            .header(RANGE, format!("bytes={}-", start))
+            .send()
+            .await
+            .map_err(error)?;
+        match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(stream::empty().boxed()),
+            // some gateways ignore the range and send everything.
+            StatusCode::OK => Ok(treefs::body(res, start)),
+            _ => Ok(treefs::body(res.error_for_status().map_err(error)?, 0)),
+        }
+    }
+
+    // url of an object, or of the bucket for an empty key.
+    fn url(&self, key: &str) -> FsResult<Url> {
+        let config = &self.config;
+        let mut url = Url::parse(&config.endpoint).map_err(|_| FsError::GeneralFailure)?;
+        let key = sign::encode_key(key);
+        if config.path_style {
+            url.set_path(&format!("/{}/{}", config.bucket, key));
+        } else {
+            let host = format!("{}.{}", config.bucket, url.host_str().unwrap_or(""));
+            url.set_host(Some(&host))
+                .map_err(|_| FsError::GeneralFailure)?;
+            url.set_path(&format!("/{}", key));
+        }
+        Ok(url)
+    }
+
+    fn request(&self, method: &str, url: Url) -> RequestBuilder {
+        let config = &self.config;
+        let signature = sign::sign(
+            method,
+            &url,
+            &config.region,
+            &config.access_key,
+            &config.secret_key,
+            UNSIGNED_PAYLOAD,
+            SystemTime::now(),
+        );
+        let method = reqwest::Method::from_bytes(method.as_bytes()).unwrap();
+        self.client
+            .request(method, url)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", signature.date)
+            .header(AUTHORIZATION, signature.authorization)
+    }
+}
+
+// a file from a <Contents> element.
+fn object(e: &Element, prefix: &str) -> Option<Entry<String>> {
+    let key = text(e, "Key")?;
+    let name = key.strip_prefix(prefix)?.to_owned();
+    // folder placeholders created by consoles.
+    if name.is_empty() || name.ends_with('/') {
+        return None;
+    }
+    let size = text(e, "Size").and_then(|s| s.parse().ok()).unwrap_or(0);
+    let mtime = text(e, "LastModified")
+        .and_then(|t| parse_time(&t))
+        .unwrap_or(UNIX_EPOCH);
+    let etag = text(e, "ETag")
+        .map(|t| t.trim_matches('"').to_owned())
+        .unwrap_or_default();
+    let content_type = mime::content_type(&name, None)
+        .unwrap_or(mime::DEFAULT)
+        .to_owned();
+    Some(Entry {
+        key,
+        name,
+        size: Some(size),
+        mtime,
+        ctime: mtime,
+        is_dir: false,
+        etag,
+        content_type,
+        follows: None,
+        media: None,
+    })
+}
+
+// a directory from a common prefix, like `prefix/name/`.
+fn directory(key: &str, prefix: &str) -> Option<Entry<String>> {
+    let name = key.strip_prefix(prefix)?.trim_end_matches('/').to_owned();
+    if name.is_empty() {
+        return None;
+    }
+    Some(Entry {
+        etag: key.to_owned(),
+        key: key.to_owned(),
+        name,
+        size: Some(0),
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        is_dir: true,
+        content_type: "httpd/unix-directory".to_owned(),
+        follows: None,
+        media: None,
+    })
+}
+
+fn text(e: &Element, name: &str) -> Option<String> {
+    e.get_child(name)
+        .and_then(|c| c.get_text())
+        .map(|t| t.into_owned())
+}
+
+// an ISO 8601 time in UTC, like 2009-10-12T17:50:30.000Z.
+fn parse_time(t: &str) -> Option<SystemTime> {
+    let num = |range: std::ops::Range<usize>| t.get(range)?.parse::<i64>().ok();
+    let date = Utc {
+        year: num(0..4)?,
+        month: num(5..7)?,
+        day: num(8..10)?,
+        hour: num(11..13)?,
+        min: num(14..16)?,
+        sec: num(17..19)?,
+    };
+    date.time()
+}
+
+fn error(e: reqwest::Error) -> FsError {
+    tracing::error!("request failed! {}", e);
+    match e.status() {
+        Some(StatusCode::NOT_FOUND) => FsError::NotFound,
+        Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN) => FsError::Forbidden,
+        _ => FsError::GeneralFailure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn last_modified() {
+        let t = parse_time("2009-10-12T17:50:30.000Z");
+        assert_eq!(t, Some(UNIX_EPOCH + Duration::from_secs(1255369830)));
+        assert_eq!(parse_time("2009-10-12"), None);
+    }
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Config {
+    /// Like `https://s3.eu-west-1.amazonaws.com`, or the url of a MinIO server.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Only serve the objects below this prefix, like `media/`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Address the bucket as `endpoint/bucket` rather than
+    /// `bucket.endpoint`, as MinIO expects.
+    #[serde(default)]
+    pub path_style: bool,
+}
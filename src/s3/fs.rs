@@ -0,0 +1,58 @@
+use std::fs;
+
+use bytes::Bytes;
+use futures::future::FutureExt;
+use webdav_handler::fs::FsFuture;
+
+use crate::options::Options;
+use crate::props::PropStore;
+use crate::s3::client::S3Client;
+use crate::s3::config::Config;
+use crate::treefs::{Backend, Chunks, Entry, TreeFs};
+
+/// Serves a bucket of S3 compatible object storage, read only. Key
+/// prefixes ending in `/` are directories, listings are cached in the
+/// tree and objects are streamed with ranged GETs.
+pub type S3FS = TreeFs<S3Client>;
+
+impl S3FS {
+    /// Create a new "S3FS" filesystem, configured by `s3.json`.
+    pub fn new(options: &Options, props: PropStore) -> Box<S3FS> {
+        let demo_config = serde_json::ser::to_string(&Config::default()).unwrap();
+        let msg = format!(
+            "s3.json does not exists, you should create with the content like:\r\n{}",
+            demo_config
+        );
+        let config_str = fs::read_to_string("s3.json").expect(msg.as_str());
+        let config = serde_json::de::from_str::<Config>(config_str.as_str()).unwrap();
+        let client = S3Client::new(config);
+        Box::new(TreeFs::with_backend(client, options, props))
+    }
+}
+
+impl Backend for S3Client {
+    // objects are keyed by their key, directories by their prefix.
+    type Key = String;
+
+    fn root(&self) -> String {
+        S3Client::root(self)
+    }
+
+    fn opendir<'a>(&'a self, prefix: &'a String) -> FsFuture<'a, Vec<Entry<String>>> {
+        S3Client::opendir(self, prefix).boxed()
+    }
+
+    fn read<'a>(&'a self, key: &'a String, start: u64, len: usize) -> FsFuture<'a, Bytes> {
+        S3Client::read(self, key, start, len).boxed()
+    }
+
+    fn open<'a>(&'a self, key: &'a String, start: u64) -> FsFuture<'a, Option<Chunks>> {
+        S3Client::open(self, key, start)
+            .map(|r| r.map(Some))
+            .boxed()
+    }
+
+    fn prop_prefix(&self) -> &'static str {
+        "s3"
+    }
+}
@@ -0,0 +1,4 @@
+mod client;
+mod config;
+pub mod fs;
+mod sign;
@@ -0,0 +1,118 @@
+use futures::{future, future::FutureExt, stream};
+use http::header::{
+    ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE,
+};
+use http::{Method, Request, Response, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::RequestBuilder;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{FsError, FsFuture};
+
+/// Playlists and `.strm` files point at phantom below this path rather
+/// than at the backend, see `Streams`.
+pub const PREFIX: &str = "/.stream/";
+
+// headers of the upstream response passed on to the player.
+const HEADERS: &[http::header::HeaderName] = &[
+    ACCEPT_RANGES,
+    CONTENT_LENGTH,
+    CONTENT_RANGE,
+    CONTENT_TYPE,
+    ETAG,
+    LAST_MODIFIED,
+];
+
+/// The media a playlist or `.strm` file points at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Media {
+    /// Id of the stream, served at `url`.
+    pub id: String,
+    /// Content type of the media itself, not of its playlist.
+    pub content_type: String,
+}
+
+/// Filesystems serving videos as playlists. The stream url is only
+/// resolved when a player opens `PREFIX`/id, so urls that expire or
+/// carry credentials never end up in a file.
+pub trait Streams: Send + Sync {
+    /// The request for the stream of the video `id`.
+    fn stream<'a>(&'a self, _id: &'a str) -> FsFuture<'a, RequestBuilder> {
+        future::err(FsError::NotFound).boxed()
+    }
+
+    /// The media behind the file at `path`, None if it's no playlist, for
+    /// frontends that play the stream directly.
+    fn media<'a>(&'a self, _path: &'a DavPath) -> FsFuture<'a, Option<Media>> {
+        future::ok(None).boxed()
+    }
+}
+
+/// The url given to players for the video `id`, on phantom at `base`.
+pub fn url(base: &str, id: &str) -> String {
+    format!(
+        "{}{}{}",
+        base,
+        PREFIX,
+        utf8_percent_encode(id, NON_ALPHANUMERIC)
+    )
+}
+
+pub fn is_stream<B>(req: &Request<B>) -> bool {
+    req.uri().path().starts_with(PREFIX)
+}
+
+/// Proxy a player's request for a stream to the backend, passing on
+/// the range it asks for.
+pub async fn serve<B>(fs: &dyn Streams, req: &Request<B>) -> Response<hyper::Body> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let id = percent_decode_str(&req.uri().path()[PREFIX.len()..]).decode_utf8_lossy();
+    let mut upstream = match fs.stream(&id).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            tracing::warn!("no stream for {}: {:?}", id, e);
+            return status(match e {
+                FsError::NotFound => StatusCode::NOT_FOUND,
+                FsError::Forbidden => StatusCode::FORBIDDEN,
+                _ => StatusCode::BAD_GATEWAY,
+            });
+        }
+    };
+    if let Some(range) = req.headers().get(RANGE) {
+        upstream = upstream.header(RANGE, range);
+    }
+    let res = match upstream.send().await {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::error!("stream of {} failed: {}", id, e);
+            return status(StatusCode::BAD_GATEWAY);
+        }
+    };
+    let mut builder = Response::builder().status(res.status());
+    for name in HEADERS {
+        if let Some(value) = res.headers().get(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    let body = if req.method() == Method::HEAD {
+        hyper::Body::empty()
+    } else {
+        hyper::Body::wrap_stream(stream::unfold(Some(res), |res| async move {
+            let mut res = res?;
+            match res.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(res))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        }))
+    };
+    builder.body(body).unwrap()
+}
+
+fn status(status: StatusCode) -> Response<hyper::Body> {
+    Response::builder()
+        .status(status)
+        .body(hyper::Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: &[&str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A time in UTC, split into the fields of its calendar date.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Utc {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub min: i64,
+    pub sec: i64,
+}
+
+impl Utc {
+    /// The date of `t`, times before the epoch are taken as the epoch.
+    pub fn new(t: SystemTime) -> Utc {
+        let secs = t
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0) as i64;
+        let (days, rem) = (secs / 86400, secs % 86400);
+        // civil date from days since the epoch, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Utc {
+            year: yoe + era * 400 + (month <= 2) as i64,
+            month,
+            day,
+            hour: rem / 3600,
+            min: rem % 3600 / 60,
+            sec: rem % 60,
+        }
+    }
+
+    /// The time of this date, None before the epoch.
+    pub fn time(&self) -> Option<SystemTime> {
+        // days since the epoch from a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let y = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let mp = (self.month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        let secs = days * 86400 + self.hour * 3600 + self.min * 60 + self.sec;
+        if secs < 0 {
+            return None;
+        }
+        Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+    }
+
+    /// The English abbreviation of the month, like `Nov`.
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[(self.month - 1) as usize]
+    }
+
+    /// Like 19941106084937.
+    pub fn compact(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}{:02}{:02}{:02}",
+            self.year, self.month, self.day, self.hour, self.min, self.sec
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_date() {
+        let t = Utc::new(UNIX_EPOCH + Duration::from_secs(784111777));
+        assert_eq!(t.compact(), "19941106084937");
+        assert_eq!(t.month_name(), "Nov");
+        let leap = Utc::new(UNIX_EPOCH + Duration::from_secs(1709251199));
+        assert_eq!(leap.compact(), "20240229235959");
+    }
+
+    #[test]
+    fn round_trip() {
+        // a day of every week until 2134, across leap years and 2100.
+        for days in (0..60000).step_by(7) {
+            let t = UNIX_EPOCH + Duration::from_secs(days * 86400 + 3723);
+            assert_eq!(Utc::new(t).time(), Some(t));
+        }
+        let t = UNIX_EPOCH + Duration::from_secs(4107542400);
+        assert_eq!(Utc::new(t).compact(), "21000301000000");
+    }
+
+    #[test]
+    fn before_the_epoch() {
+        assert_eq!(
+            Utc::new(UNIX_EPOCH - Duration::from_secs(1)).compact(),
+            "19700101000000"
+        );
+        let t = Utc {
+            year: 1969,
+            month: 12,
+            day: 31,
+            hour: 23,
+            min: 59,
+            sec: 59,
+        };
+        assert_eq!(t.time(), None);
+    }
+}
@@ -1,7 +1,8 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::time::{Duration, Instant};
 use webdav_handler::fs::{FsError, FsResult};
 
 #[derive(Debug)]
@@ -14,6 +15,14 @@ pub struct Tree<K: Eq + Hash, D> {
 /// id of the root node of the tree.
 pub const ROOT_ID: u64 = 1;
 
+// how long a listing is used before it's fetched again, so changes on
+// the backend show up.
+const LISTING_TTL: Duration = Duration::from_secs(600);
+
+// backoff between retries of a failed listing.
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(600);
+
 #[derive(Debug)]
 /// Node itself. "data" contains user-modifiable data.
 pub struct Node<K: Eq + Hash, D> {
@@ -21,6 +30,67 @@ pub struct Node<K: Eq + Hash, D> {
     id: u64,
     parent_id: u64,
     children: HashMap<K, u64>,
+    links: usize,
+    atime: Instant,
+    listing: Listing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Whether the children of a node have been fetched from the backend.
+pub enum Listing {
+    /// Never listed, or the children have been evicted.
+    NotListed,
+    /// A listing is in flight.
+    InProgress { failures: u32 },
+    /// Listed at the given time.
+    Listed(Instant),
+    /// The last listing failed, don't try again before `retry_at`.
+    Failed {
+        error: FsError,
+        retry_at: Instant,
+        failures: u32,
+    },
+}
+
+impl Listing {
+    /// State when a new listing starts.
+    pub fn start(&self) -> Listing {
+        match *self {
+            Listing::Failed { failures, .. } => Listing::InProgress { failures },
+            _ => Listing::InProgress { failures: 0 },
+        }
+    }
+
+    /// State after a failed listing. The retry delay doubles with
+    /// every consecutive failure.
+    pub fn fail(&self, error: FsError) -> Listing {
+        let failures = match *self {
+            Listing::InProgress { failures } | Listing::Failed { failures, .. } => failures + 1,
+            _ => 1,
+        };
+        let delay = RETRY_MIN
+            .checked_mul(1 << (failures - 1).min(16))
+            .unwrap_or(RETRY_MAX)
+            .min(RETRY_MAX);
+        Listing::Failed {
+            error,
+            retry_at: Instant::now() + delay,
+            failures,
+        }
+    }
+
+    /// Check if the children are available without asking the backend.
+    /// Returns the cached error while a failed listing is backing off,
+    /// and None once a listing is older than `LISTING_TTL`.
+    pub fn cached(&self) -> Option<FsResult<()>> {
+        match *self {
+            Listing::Listed(at) if at.elapsed() < LISTING_TTL => Some(Ok(())),
+            Listing::Failed {
+                error, retry_at, ..
+            } if Instant::now() < retry_at => Some(Err(error)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -32,7 +102,7 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
     pub fn new(data: D) -> Tree<K, D> {
         let mut t = Tree {
             nodes: HashMap::new(),
-            node_id: ROOT_ID,
+            node_id: ROOT_ID + 1,
         };
         t.new_node(1, 99999999, data);
         t
@@ -53,12 +123,19 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
             parent_id: parent,
             data,
             children: HashMap::new(),
+            links: 0,
+            atime: Instant::now(),
+            listing: Listing::NotListed,
         };
         self.nodes.insert(id, node);
         id
     }
 
     /// add a child node to an existing node.
+    ///
+    /// If a node with the given `id` exists already, its data is updated
+    /// in place and it is linked under `parent` as well, so the same
+    /// node can show up in several directories.
     pub fn add_child(
         &mut self,
         id: u64,
@@ -69,17 +146,51 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
     ) -> FsResult<u64> {
         {
             let pnode = self.nodes.get(&parent).ok_or(FsError::NotFound)?;
-            if !overwrite && pnode.children.contains_key(&key) {
-                return Err(FsError::Exists);
+            match pnode.children.get(&key) {
+                Some(&cid) if id != 0 && cid == id => {
+                    self.nodes.get_mut(&id).unwrap().data = data;
+                    return Ok(id);
+                }
+                Some(_) if !overwrite => return Err(FsError::Exists),
+                _ => {}
             }
         }
-        let id = self.new_node(id, parent, data);
+        let id = match self.nodes.get_mut(&id) {
+            Some(node) if id != 0 => {
+                node.data = data;
+                id
+            }
+            _ => self.new_node(id, parent, data),
+        };
+        self.nodes.get_mut(&id).unwrap().links += 1;
         let pnode = self.nodes.get_mut(&parent).unwrap();
 
-        pnode.children.insert(key, id);
+        if let Some(old) = pnode.children.insert(key, id) {
+            self.unlink(vec![old]);
+        }
         Ok(id)
     }
 
+    // drop one link to each of the nodes, removing the ones that are
+    // not linked anywhere anymore together with their descendants.
+    // Returns the number of removed nodes.
+    fn unlink(&mut self, mut stack: Vec<u64>) -> usize {
+        let mut removed = 0;
+        while let Some(c) = stack.pop() {
+            let n = match self.nodes.get_mut(&c) {
+                Some(n) => n,
+                None => continue,
+            };
+            n.links -= 1;
+            if n.links == 0 {
+                let n = self.nodes.remove(&c).unwrap();
+                stack.extend(n.children.values());
+                removed += 1;
+            }
+        }
+        removed
+    }
+
     /// Get a child node by key K.
     pub fn get_child<Q: ?Sized>(&self, parent: u64, key: &Q) -> FsResult<u64>
     where
@@ -101,6 +212,26 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
         Ok(Children(v.into_iter()))
     }
 
+    /// Mark a node as recently visited, so it is the last to be evicted.
+    pub fn touch(&mut self, id: u64) -> FsResult<()> {
+        let n = self.nodes.get_mut(&id).ok_or(FsError::NotFound)?;
+        n.atime = Instant::now();
+        Ok(())
+    }
+
+    /// Get the listing state of a node.
+    pub fn listing(&self, id: u64) -> FsResult<Listing> {
+        let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
+        Ok(n.listing)
+    }
+
+    /// Set the listing state of a node.
+    pub fn set_listing(&mut self, id: u64, listing: Listing) -> FsResult<()> {
+        let n = self.nodes.get_mut(&id).ok_or(FsError::NotFound)?;
+        n.listing = listing;
+        Ok(())
+    }
+
     /// Get reference to a node.
     pub fn get_node(&self, id: u64) -> FsResult<&D> {
         let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
@@ -113,79 +244,75 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
         Ok(&mut n.data)
     }
 
-    fn delete_node_from_parent(&mut self, id: u64) -> FsResult<()> {
-        let parent_id = self.nodes.get(&id).ok_or(FsError::NotFound)?.parent_id;
-        let key = {
-            let pnode = self.nodes.get(&parent_id).unwrap();
-            let mut key = None;
-            for (k, i) in &pnode.children {
-                if i == &id {
-                    key = Some((*k).clone());
-                    break;
-                }
-            }
-            key
+    /// Unlink the children of a node whose key is not in `keep`, like
+    /// the ones gone from the backend when it's listed again. Returns the
+    /// number of removed nodes.
+    pub fn retain_children(&mut self, id: u64, keep: &HashSet<K>) -> FsResult<usize> {
+        let gone = {
+            let n = self.nodes.get_mut(&id).ok_or(FsError::NotFound)?;
+            let gone = n
+                .children
+                .keys()
+                .filter(|k| !keep.contains(*k))
+                .cloned()
+                .collect::<Vec<K>>();
+            gone.iter()
+                .filter_map(|k| n.children.remove(k))
+                .collect::<Vec<u64>>()
         };
-        let key = key.unwrap();
-        let pnode = self.nodes.get_mut(&parent_id).unwrap();
-        pnode.children.remove(&key);
-        Ok(())
+        Ok(self.unlink(gone))
     }
 
-    /// Delete a node. Fails if node has children. Returns node itself.
-    pub fn delete_node(&mut self, id: u64) -> FsResult<Node<K, D>> {
-        {
-            let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
-            if n.children.len() > 0 {
-                return Err(FsError::Forbidden);
-            }
-        }
-        self.delete_node_from_parent(id)?;
-        Ok(self.nodes.remove(&id).unwrap())
-    }
-
-    /// Delete a subtree.
-    pub fn delete_subtree(&mut self, id: u64) -> FsResult<()> {
+    /// Remove all descendants of a node, keeping the node itself,
+    /// and mark it as not listed. Descendants that are also linked
+    /// from elsewhere are kept. Returns the number of removed nodes.
+    pub fn clear_children(&mut self, id: u64) -> FsResult<usize> {
         let children = {
-            let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
-            n.children.iter().map(|(_, &v)| v).collect::<Vec<u64>>()
+            let n = self.nodes.get_mut(&id).ok_or(FsError::NotFound)?;
+            n.listing = Listing::NotListed;
+            n.children.drain().map(|(_, v)| v).collect::<Vec<u64>>()
         };
-        for c in children.into_iter() {
-            self.delete_subtree(c)?;
-        }
-        self.delete_node_from_parent(id)
+        Ok(self.unlink(children))
     }
 
-    /// Move a node to a new position and new name in the tree.
-    /// If "overwrite" is true, will replace an existing
-    /// node, but only if it doesn't have any children.
-    pub fn move_node(
-        &mut self,
-        id: u64,
-        new_parent: u64,
-        new_name: K,
-        overwrite: bool,
-    ) -> FsResult<()> {
-        let dest = {
-            let pnode = self.nodes.get(&new_parent).ok_or(FsError::NotFound)?;
-            if let Some(cid) = pnode.children.get(&new_name) {
-                let cnode = self.nodes.get(cid).unwrap();
-                if !overwrite || cnode.children.len() > 0 {
-                    return Err(FsError::Exists);
-                }
-                Some(*cid)
-            } else {
-                None
+    /// Evict the children of the least recently touched directories
+    /// when the tree holds more than `max_nodes` nodes. To avoid
+    /// evicting on every insert, the tree is trimmed to three quarters
+    /// of the limit. The root, and `keep` with its ancestors, are never
+    /// cleared, so the directory being listed stays reachable. Returns the
+    /// number of removed nodes.
+    pub fn evict(&mut self, max_nodes: usize, keep: u64) -> usize {
+        let mut evicted = 0;
+        if self.nodes.len() <= max_nodes {
+            return evicted;
+        }
+        let target = max_nodes - max_nodes / 4;
+        let mut path = HashSet::new();
+        let mut id = keep;
+        while let Some(n) = self.nodes.get(&id) {
+            if !path.insert(id) || id == ROOT_ID {
+                break;
             }
-        };
-        self.delete_node_from_parent(id)?;
-        self.nodes.get_mut(&id).unwrap().parent_id = new_parent;
-        if let Some(dest) = dest {
-            self.nodes.remove(&dest);
+            id = n.parent_id;
         }
-        let pnode = self.nodes.get_mut(&new_parent).unwrap();
-        pnode.children.insert(new_name, id);
-        Ok(())
+        let mut dirs = self
+            .nodes
+            .values()
+            .filter(|n| !n.children.is_empty())
+            .filter(|n| n.id != ROOT_ID && !path.contains(&n.id))
+            .map(|n| (n.atime, n.id))
+            .collect::<Vec<(Instant, u64)>>();
+        dirs.sort();
+        for (_, id) in dirs {
+            if self.nodes.len() <= target {
+                break;
+            }
+            // might already be gone together with an evicted ancestor.
+            if let Ok(removed) = self.clear_children(id) {
+                evicted += removed;
+            }
+        }
+        evicted
     }
 }
 
@@ -195,3 +322,78 @@ impl<K> Iterator for Children<K> {
         self.0.next()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_keeps_root_and_ancestors() {
+        let mut tree = Tree::new(());
+        let a = tree
+            .add_child(0, ROOT_ID, "a".to_owned(), (), false)
+            .unwrap();
+        let x = tree.add_child(0, a, "x".to_owned(), (), false).unwrap();
+        let b = tree
+            .add_child(0, ROOT_ID, "b".to_owned(), (), false)
+            .unwrap();
+        for i in 0..10 {
+            tree.add_child(0, x, format!("x{}", i), (), false).unwrap();
+            tree.add_child(0, b, format!("b{}", i), (), false).unwrap();
+        }
+        // `b` is the most recently used, but `x` is being listed.
+        tree.touch(b).unwrap();
+
+        assert_eq!(tree.evict(20, x), 10);
+        assert_eq!(tree.get_child(ROOT_ID, "a").unwrap(), a);
+        assert_eq!(tree.get_child(ROOT_ID, "b").unwrap(), b);
+        assert_eq!(tree.get_children(x).unwrap().count(), 10);
+        assert_eq!(tree.get_children(b).unwrap().count(), 0);
+        assert!(tree.listing(b).unwrap().cached().is_none());
+    }
+
+    #[test]
+    fn retain_children_unlinks_the_rest() {
+        let mut tree = Tree::new(());
+        let a = tree
+            .add_child(0, ROOT_ID, "a".to_owned(), (), false)
+            .unwrap();
+        tree.add_child(0, a, "a1".to_owned(), (), false).unwrap();
+        tree.add_child(0, ROOT_ID, "b".to_owned(), (), false)
+            .unwrap();
+
+        let keep = ["b".to_owned()].iter().cloned().collect();
+        assert_eq!(tree.retain_children(ROOT_ID, &keep).unwrap(), 2);
+        assert!(tree.get_child(ROOT_ID, "a").is_err());
+        assert!(tree.get_node(a).is_err());
+        assert!(tree.get_child(ROOT_ID, "b").is_ok());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut listing = Listing::NotListed;
+        let mut delays = Vec::new();
+        for _ in 0..10 {
+            listing = listing.start().fail(FsError::GeneralFailure);
+            match listing {
+                Listing::Failed { retry_at, .. } => {
+                    delays.push((retry_at - Instant::now()).as_secs_f64().round() as u64)
+                }
+                _ => panic!("not failed: {:?}", listing),
+            }
+            assert!(matches!(
+                listing.cached(),
+                Some(Err(FsError::GeneralFailure))
+            ));
+        }
+        assert_eq!(delays, vec![5, 10, 20, 40, 80, 160, 320, 600, 600, 600]);
+    }
+
+    #[test]
+    fn listing_expires() {
+        assert!(Listing::Listed(Instant::now()).cached().is_some());
+        if let Some(at) = Instant::now().checked_sub(LISTING_TTL) {
+            assert!(Listing::Listed(at).cached().is_none());
+        }
+    }
+}
@@ -0,0 +1,784 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt};
+use futures::{future, future::BoxFuture, future::FutureExt};
+use http::StatusCode;
+use reqwest::RequestBuilder;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{
+    DavDirEntry, DavFile, DavFileSystem, DavMetaData, DavProp, FsError, FsFuture, FsResult,
+    FsStream, OpenOptions, ReadDirMeta,
+};
+
+use crate::cache::ContentCache;
+use crate::flight::SingleFlight;
+use crate::fuse::Inodes;
+use crate::mime::ContentTypes;
+use crate::names::{self, Sanitize};
+use crate::options::Options;
+use crate::props::PropStore;
+use crate::quota::{Quota, QuotaCache};
+use crate::stream::{Media, Streams};
+use crate::tree;
+use crate::tree::Listing;
+
+/// Identifies a file or directory on a backend, like a path or an
+/// object key.
+pub trait Key: Clone + Eq + Hash + Ord + Debug + Send + Sync + 'static {
+    /// The key as text, for the property store and the logs.
+    fn as_text(&self) -> Cow<'_, str>;
+}
+
+impl Key for String {
+    fn as_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Key for PathBuf {
+    fn as_text(&self) -> Cow<'_, str> {
+        self.to_string_lossy()
+    }
+}
+
+/// A file or directory in a listing of a backend.
+pub struct Entry<K> {
+    pub key: K,
+    pub name: String,
+    /// None for files the listing has no size for, it's asked for with
+    /// `Backend::size` once the file is used.
+    pub size: Option<u64>,
+    pub mtime: SystemTime,
+    pub ctime: SystemTime,
+    pub is_dir: bool,
+    pub etag: String,
+    pub content_type: String,
+    /// For a file that goes with another one in the listing, like the
+    /// subtitles of a video: the key of that file and the rest of this
+    /// name after its stem, so the names stay together when it's renamed
+    /// to be unique.
+    pub follows: Option<(K, String)>,
+    /// The media behind a playlist or `.strm` file, see `Streams::media`.
+    pub media: Option<Media>,
+}
+
+/// The content of a file from some offset to its end, as it arrives.
+pub type Chunks = BoxStream<'static, FsResult<Bytes>>;
+
+/// A read only store of files that can be served by `TreeFs`.
+pub trait Backend: Debug + Send + Sync + 'static {
+    type Key: Key;
+
+    /// How long the quota reported by the backend is reused.
+    const QUOTA_TTL: Duration = Duration::from_secs(60);
+
+    /// Key of the directory served at the root.
+    fn root(&self) -> Self::Key;
+
+    /// List the directory `key`.
+    fn opendir<'a>(&'a self, key: &'a Self::Key) -> FsFuture<'a, Vec<Entry<Self::Key>>>;
+
+    /// Read up to `len` bytes from `start` of the file `key`.
+    fn read<'a>(&'a self, key: &'a Self::Key, start: u64, len: usize) -> FsFuture<'a, Bytes>;
+
+    /// Open the file `key` from `start` to its end. Sequential reads are
+    /// served from the stream, instead of a `read` each. Backends that
+    /// can't stream return None.
+    fn open<'a>(&'a self, _key: &'a Self::Key, _start: u64) -> FsFuture<'a, Option<Chunks>> {
+        future::ok(None).boxed()
+    }
+
+    /// Size of the file `key`, listed without one. Backends that have to
+    /// download the file to tell return its content as well.
+    fn size<'a>(&'a self, _key: &'a Self::Key) -> FsFuture<'a, (u64, Option<Bytes>)> {
+        future::err(FsError::NotImplemented).boxed()
+    }
+
+    /// Prefix of the keys of the backend in the property store.
+    fn prop_prefix(&self) -> &'static str;
+
+    /// Key of the file `key` in the property store.
+    fn prop_key(&self, key: &Self::Key) -> String {
+        format!("{}:{}", self.prop_prefix(), key.as_text())
+    }
+
+    /// Space used and the total, if the backend knows them.
+    fn quota(&self) -> FsFuture<'_, Quota> {
+        future::err(FsError::NotImplemented).boxed()
+    }
+
+    /// The request for the stream of the video `id`, for backends that
+    /// serve videos as playlists, see `Streams`.
+    fn stream<'a>(&'a self, _id: &'a str) -> FsFuture<'a, RequestBuilder> {
+        future::err(FsError::NotFound).boxed()
+    }
+}
+
+type Tree<K> = tree::Tree<Vec<u8>, FSNode<K>>;
+
+/// A read only filesystem over a `Backend`. Listings are cached in the
+/// tree and re-fetched once evicted, file content is read from the
+/// backend as requested.
+#[derive(Debug)]
+pub struct TreeFs<B: Backend> {
+    backend: Arc<B>,
+    tree: Arc<Mutex<Tree<B::Key>>>,
+    // content downloaded to size a file, see `Backend::size`.
+    cache: Arc<ContentCache>,
+    listings: Arc<SingleFlight<u64, FsResult<()>>>,
+    props: PropStore,
+    quota: Arc<QuotaCache>,
+    // backend key -> tree node id, so files keep their node across
+    // refreshes. Entries of evicted nodes are dropped.
+    ids: Arc<Mutex<HashMap<B::Key, u64>>>,
+    max_nodes: usize,
+    sanitize: Sanitize,
+}
+
+#[derive(Debug, Clone)]
+enum FSNode<K> {
+    Dir(FSDirNode<K>),
+    File(FSFileNode<K>),
+}
+
+#[derive(Debug, Clone)]
+struct FSDirNode<K> {
+    etag: Option<String>,
+    key: K,
+    mtime: SystemTime,
+    crtime: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+struct FSFileNode<K> {
+    etag: Option<String>,
+    mtime: SystemTime,
+    crtime: SystemTime,
+    key: K,
+    size: Option<u64>,
+    content_type: String,
+    media: Option<Media>,
+}
+
+#[derive(Debug, Clone)]
+struct FSEntry {
+    etag: Option<String>,
+    content_type: Option<String>,
+    mtime: SystemTime,
+    crtime: SystemTime,
+    is_dir: bool,
+    name: Vec<u8>,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct FSFile<B: Backend> {
+    tree: Arc<Mutex<Tree<B::Key>>>,
+    cache: Arc<ContentCache>,
+    backend: Arc<B>,
+    node_id: u64,
+    key: B::Key,
+    pos: u64,
+    // the stream of the last read, reused while reads are sequential.
+    // Only in a mutex to be Sync, like DavFile has to be.
+    reader: Mutex<Option<Reader>>,
+}
+
+// an open stream of a file.
+struct Reader {
+    chunks: Chunks,
+    // offset of the next byte, the first one of `buf`.
+    pos: u64,
+    buf: Bytes,
+}
+
+/// The body of a response as `Chunks`, without its first `skip` bytes,
+/// for servers that ignore the range of a request.
+pub fn body(res: reqwest::Response, skip: u64) -> Chunks {
+    let mut skip = skip;
+    stream::unfold(Some(res), |res| async move {
+        let mut res = res?;
+        match res.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(res))),
+            Ok(None) => None,
+            Err(e) => {
+                tracing::error!("failed to read the response: {}", e);
+                Some((Err(FsError::GeneralFailure), None))
+            }
+        }
+    })
+    .filter_map(move |chunk| {
+        let chunk = match chunk {
+            Ok(chunk) if skip >= chunk.len() as u64 => {
+                skip -= chunk.len() as u64;
+                None
+            }
+            Ok(chunk) => {
+                let chunk = chunk.slice(skip as usize..);
+                skip = 0;
+                Some(Ok(chunk))
+            }
+            Err(e) => Some(Err(e)),
+        };
+        future::ready(chunk)
+    })
+    .boxed()
+}
+
+impl<B: Backend> TreeFs<B> {
+    /// Create a new filesystem serving `backend`.
+    pub fn with_backend(backend: B, options: &Options, props: PropStore) -> TreeFs<B> {
+        let cache = ContentCache::new(options.cache_size);
+        TreeFs::build(backend, Arc::new(cache), props, options.max_nodes, options.sanitize)
+    }
+
+    fn build(
+        backend: B,
+        cache: Arc<ContentCache>,
+        props: PropStore,
+        max_nodes: usize,
+        sanitize: Sanitize,
+    ) -> TreeFs<B> {
+        let root = FSNode::Dir(FSDirNode {
+            etag: None,
+            key: backend.root(),
+            crtime: SystemTime::now(),
+            mtime: SystemTime::now(),
+        });
+        TreeFs {
+            backend: Arc::new(backend),
+            tree: Arc::new(Mutex::new(Tree::new(root))),
+            cache,
+            listings: Arc::new(SingleFlight::new()),
+            props,
+            quota: Arc::new(QuotaCache::new(B::QUOTA_TTL)),
+            ids: Arc::new(Mutex::new(HashMap::new())),
+            max_nodes,
+            sanitize,
+        }
+    }
+
+    /// The same filesystem over the backend `f` makes of this one's, like
+    /// one with local directories merged in. Only for a filesystem that
+    /// hasn't been served yet.
+    pub fn map_backend<C: Backend>(self, f: impl FnOnce(B) -> C) -> TreeFs<C> {
+        let backend = Arc::try_unwrap(self.backend).expect("backend already in use");
+        TreeFs::build(f(backend), self.cache, self.props, self.max_nodes, self.sanitize)
+    }
+
+    // list a directory from the backend, unless that has been done already.
+    // Concurrent calls for the same directory share one request.
+    async fn list(&self, node_id: u64) -> FsResult<()> {
+        let key = {
+            let tree = &mut *self.tree.lock().unwrap();
+            let key = tree.get_node(node_id)?.as_dir()?.key.clone();
+            tree.touch(node_id)?;
+            if let Some(res) = tree.listing(node_id)?.cached() {
+                return res;
+            }
+            key
+        };
+        let fs = self.clone();
+        self.listings
+            .run(node_id, move || async move { fs.load(node_id, &key).await })
+            .await
+    }
+
+    // fetch a directory from the backend and merge it into the tree.
+    // The tree is only locked once the response is in.
+    async fn load(&self, node_id: u64, key: &B::Key) -> FsResult<()> {
+        {
+            let tree = &mut *self.tree.lock().unwrap();
+            let listing = tree.listing(node_id)?;
+            // another caller may have completed the listing meanwhile.
+            if let Some(res) = listing.cached() {
+                return res;
+            }
+            tree.set_listing(node_id, listing.start())?;
+        }
+        let res = self.backend.opendir(key).await;
+        let tree = &mut *self.tree.lock().unwrap();
+        let mut entries = match res {
+            Ok(entries) => entries,
+            Err(e) => {
+                let listing = tree.listing(node_id)?;
+                tree.set_listing(node_id, listing.fail(e))?;
+                return Err(e);
+            }
+        };
+        // sort by key, so duplicate names get numbered the same way every time.
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let index = entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (&e.key, i))
+            .collect::<HashMap<_, _>>();
+        let follows = entries
+            .iter()
+            .map(|e| {
+                let (key, rest) = e.follows.as_ref()?;
+                Some((*index.get(key)?, rest.as_str()))
+            })
+            .collect::<Vec<_>>();
+        let given = entries
+            .iter()
+            .zip(follows)
+            .map(|(e, follows)| (e.name.as_str(), follows))
+            .collect::<Vec<_>>();
+        let names = names::unique_following(&given, self.sanitize);
+        let ids = &mut *self.ids.lock().unwrap();
+        let mut listed = HashSet::new();
+        for (entry, name) in entries.into_iter().zip(names) {
+            let node = if entry.is_dir {
+                FSNode::Dir(FSDirNode {
+                    etag: Some(entry.etag),
+                    key: entry.key.clone(),
+                    crtime: entry.ctime,
+                    mtime: entry.mtime,
+                })
+            } else {
+                FSNode::File(FSFileNode {
+                    etag: Some(entry.etag),
+                    crtime: entry.ctime,
+                    mtime: entry.mtime,
+                    key: entry.key.clone(),
+                    size: entry.size,
+                    content_type: entry.content_type,
+                    media: entry.media,
+                })
+            };
+            let known_id = ids.get(&entry.key).copied().unwrap_or(0);
+            let name = name.into_bytes();
+            // a name taken over by another file replaces it.
+            match tree.add_child(known_id, node_id, name.clone(), node, true) {
+                Ok(id) => {
+                    ids.insert(entry.key, id);
+                    listed.insert(name);
+                }
+                Err(e) => tracing::warn!(
+                    "failed to add {} to {}: {:?}",
+                    entry.key.as_text(),
+                    node_id,
+                    e
+                ),
+            }
+        }
+        // drop what is gone since the last listing.
+        let mut evicted = tree.retain_children(node_id, &listed)?;
+        tree.set_listing(node_id, Listing::Listed(Instant::now()))?;
+        evicted += tree.evict(self.max_nodes, node_id);
+        if evicted > 0 {
+            tracing::info!("evicted {} nodes", evicted);
+            ids.retain(|_, id| tree.get_node(*id).is_ok());
+        }
+        Ok(())
+    }
+
+    // resolve a path to a node id, listing the directories on the way
+    // that were never listed or have been evicted since.
+    async fn resolve(&self, path: &[u8]) -> FsResult<u64> {
+        let mut node_id = tree::ROOT_ID;
+        for seg in path.split(|&c| c == b'/').filter(|s| !s.is_empty()) {
+            self.list(node_id).await?;
+            let tree = &*self.tree.lock().unwrap();
+            node_id = tree.get_child(node_id, seg)?;
+        }
+        Ok(node_id)
+    }
+
+    // ask for the size of a file listed without one, before it's read.
+    async fn sized(&self, node_id: u64) -> FsResult<()> {
+        let key = {
+            let tree = &*self.tree.lock().unwrap();
+            match tree.get_node(node_id)? {
+                FSNode::File(FSFileNode {
+                    size: None, key, ..
+                }) => key.clone(),
+                _ => return Ok(()),
+            }
+        };
+        let (size, data) = self.backend.size(&key).await?;
+        let tree = &mut *self.tree.lock().unwrap();
+        if let FSNode::File(file) = tree.get_node_mut(node_id)? {
+            file.size = Some(size);
+        }
+        if let Some(data) = data {
+            self.cache.put(node_id, data);
+        }
+        Ok(())
+    }
+
+    // key of a node in the property store.
+    async fn prop_key(&self, path: &DavPath) -> FsResult<String> {
+        let node_id = self.resolve(path.as_bytes()).await?;
+        let tree = &*self.tree.lock().unwrap();
+        let key = match tree.get_node(node_id)? {
+            FSNode::Dir(d) => &d.key,
+            FSNode::File(f) => &f.key,
+        };
+        Ok(self.backend.prop_key(key))
+    }
+}
+
+impl<B: Backend> Clone for TreeFs<B> {
+    fn clone(&self) -> Self {
+        TreeFs {
+            backend: self.backend.clone(),
+            tree: Arc::clone(&self.tree),
+            cache: Arc::clone(&self.cache),
+            listings: Arc::clone(&self.listings),
+            props: self.props.clone(),
+            quota: Arc::clone(&self.quota),
+            ids: Arc::clone(&self.ids),
+            max_nodes: self.max_nodes,
+            sanitize: self.sanitize,
+        }
+    }
+}
+
+impl<B: Backend> DavFileSystem for TreeFs<B> {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            if options.create_new {
+                return Err(FsError::Exists);
+            }
+            if options.write || options.append || options.truncate || options.create {
+                return Err(FsError::Forbidden);
+            }
+            let node_id = self.resolve(path.as_bytes()).await?;
+            self.sized(node_id).await?;
+            let key = {
+                let tree = &*self.tree.lock().unwrap();
+                tree.get_node(node_id)?.as_file()?.key.clone()
+            };
+            Ok(Box::new(FSFile {
+                tree: self.tree.clone(),
+                cache: self.cache.clone(),
+                backend: self.backend.clone(),
+                node_id,
+                key,
+                pos: 0,
+                reader: Mutex::new(None),
+            }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        _meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        async move {
+            let node_id = self.resolve(path.as_bytes()).await?;
+            self.list(node_id).await?;
+            let tree = &*self.tree.lock().unwrap();
+
+            let mut v: Vec<Box<dyn DavDirEntry>> = Vec::new();
+            for (name, dnode_id) in tree.get_children(node_id)? {
+                if let Ok(node) = tree.get_node(dnode_id) {
+                    v.push(Box::new(node.as_dirent(&name)));
+                }
+            }
+            let strm = futures::stream::iter(v);
+            Ok(Box::pin(strm) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let node_id = self.resolve(path.as_bytes()).await?;
+            self.sized(node_id).await?;
+            let tree = &*self.tree.lock().unwrap();
+            let meta = tree.get_node(node_id)?.as_dirent(path.as_bytes());
+            Ok(Box::new(meta) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn have_props<'a>(&'a self, _path: &'a DavPath) -> BoxFuture<'a, bool> {
+        future::ready(true).boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        async move {
+            let key = self.prop_key(path).await?;
+            self.props.patch_props(&key, patch).await
+        }
+        .boxed()
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        async move {
+            let key = self.prop_key(path).await?;
+            self.props.get_props(&key, do_content)
+        }
+        .boxed()
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        async move {
+            let key = self.prop_key(path).await?;
+            self.props.get_prop(&key, &prop)
+        }
+        .boxed()
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, Quota> {
+        async move {
+            let backend = self.backend.clone();
+            self.quota
+                .get(move || async move { backend.quota().await })
+                .await
+        }
+        .boxed()
+    }
+}
+
+impl<B: Backend> ContentTypes for TreeFs<B> {
+    fn content_type<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move {
+            let node_id = self.resolve(path.as_bytes()).await?;
+            let tree = &*self.tree.lock().unwrap();
+            let meta = tree.get_node(node_id)?.as_dirent(path.as_bytes());
+            meta.content_type.ok_or(FsError::Forbidden)
+        }
+        .boxed()
+    }
+}
+
+impl<B: Backend> Streams for TreeFs<B> {
+    fn stream<'a>(&'a self, id: &'a str) -> FsFuture<'a, RequestBuilder> {
+        self.backend.stream(id)
+    }
+
+    fn media<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<Media>> {
+        async move {
+            let node_id = self.resolve(path.as_bytes()).await?;
+            let tree = self.tree.lock().unwrap();
+            match tree.get_node(node_id)? {
+                FSNode::File(file) => Ok(file.media.clone()),
+                FSNode::Dir(_) => Ok(None),
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<B: Backend> Inodes for TreeFs<B> {
+    fn node_id<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, u64> {
+        async move { self.resolve(path.as_bytes()).await }.boxed()
+    }
+}
+
+impl DavDirEntry for FSEntry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = (*self).clone();
+        Box::pin(future::ok(Box::new(meta) as Box<dyn DavMetaData>))
+    }
+}
+
+impl<B: Backend> DavFile for FSFile<B> {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        async move {
+            let tree = &*self.tree.lock().unwrap();
+            let node = tree.get_node(self.node_id)?;
+            let meta = node.as_dirent(b"");
+            Ok(Box::new(meta) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn write_buf(&mut self, _buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        async move { Err(Error::new(ErrorKind::PermissionDenied, "read only fs").into()) }.boxed()
+    }
+
+    fn write_bytes(&mut self, _buf: Bytes) -> FsFuture<'_, ()> {
+        async move { Err(Error::new(ErrorKind::PermissionDenied, "read only fs").into()) }.boxed()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        async move {
+            let pos = self.pos;
+            if let Some(data) = self.cache.get(self.node_id) {
+                let start = (pos as usize).min(data.len());
+                let end = (start + count).min(data.len());
+                self.pos += (end - start) as u64;
+                return Ok(data.slice(start..end));
+            }
+            let reader = self.reader.get_mut().unwrap();
+            // the stream can't go back, after a seek it's opened again.
+            if !matches!(reader, Some(r) if r.pos == pos) {
+                *reader = self
+                    .backend
+                    .open(&self.key, pos)
+                    .await?
+                    .map(|chunks| Reader {
+                        chunks,
+                        pos,
+                        buf: Bytes::new(),
+                    });
+            }
+            let res = match reader {
+                Some(r) => r.read(count).await,
+                None => self.backend.read(&self.key, pos, count).await,
+            };
+            let data = match res {
+                Ok(data) => data,
+                Err(e) => {
+                    // reopen after a broken stream.
+                    *reader = None;
+                    return Err(e);
+                }
+            };
+            self.pos += data.len() as u64;
+            Ok(data)
+        }
+        .boxed()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        async move {
+            let (start, offset): (u64, i64) = match pos {
+                SeekFrom::Start(npos) => {
+                    self.pos = npos;
+                    return Ok(npos);
+                }
+                SeekFrom::Current(npos) => (self.pos, npos),
+                SeekFrom::End(npos) => {
+                    let tree = &*self.tree.lock().unwrap();
+                    let node = tree.get_node(self.node_id)?;
+                    (node.as_file()?.size.unwrap_or(0), npos)
+                }
+            };
+            if offset < 0 {
+                if -offset as u64 > start {
+                    return Err(Error::new(ErrorKind::InvalidInput, "invalid seek").into());
+                }
+                self.pos = start - (-offset as u64);
+            } else {
+                self.pos = start + offset as u64;
+            }
+            Ok(self.pos)
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        future::ok(()).boxed()
+    }
+}
+
+impl Reader {
+    // read `count` bytes, or less at the end of the file.
+    async fn read(&mut self, count: usize) -> FsResult<Bytes> {
+        if self.buf.is_empty() {
+            self.buf = self.chunks.next().await.transpose()?.unwrap_or_default();
+        }
+        // mostly served from a single chunk, without copying.
+        if self.buf.len() >= count || self.buf.is_empty() {
+            let data = self.buf.split_to(count.min(self.buf.len()));
+            self.pos += data.len() as u64;
+            return Ok(data);
+        }
+        let mut data = BytesMut::with_capacity(count);
+        while data.len() < count && !self.buf.is_empty() {
+            let n = (count - data.len()).min(self.buf.len());
+            data.extend_from_slice(&self.buf.split_to(n));
+            if self.buf.is_empty() {
+                self.buf = self.chunks.next().await.transpose()?.unwrap_or_default();
+            }
+        }
+        self.pos += data.len() as u64;
+        Ok(data.freeze())
+    }
+}
+
+impl Debug for Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reader").field("pos", &self.pos).finish()
+    }
+}
+
+impl DavMetaData for FSEntry {
+    fn len(&self) -> u64 {
+        self.size
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.mtime)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn created(&self) -> FsResult<SystemTime> {
+        Ok(self.crtime)
+    }
+
+    fn etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+}
+
+impl<K> FSNode<K> {
+    // helper to create FSEntry from a node.
+    fn as_dirent(&self, name: &[u8]) -> FSEntry {
+        let (is_dir, size, mtime, crtime, etag, content_type) = match self {
+            FSNode::File(file) => (
+                false,
+                file.size.unwrap_or(0),
+                file.mtime,
+                file.crtime,
+                &file.etag,
+                Some(file.content_type.clone()),
+            ),
+            FSNode::Dir(dir) => (true, 0, dir.mtime, dir.crtime, &dir.etag, None),
+        };
+        FSEntry {
+            etag: etag.clone(),
+            content_type,
+            name: name.to_vec(),
+            mtime,
+            crtime,
+            is_dir,
+            size,
+        }
+    }
+
+    fn as_dir(&self) -> FsResult<&FSDirNode<K>> {
+        match self {
+            FSNode::Dir(n) => Ok(n),
+            _ => Err(FsError::Forbidden),
+        }
+    }
+
+    fn as_file(&self) -> FsResult<&FSFileNode<K>> {
+        match self {
+            FSNode::File(n) => Ok(n),
+            _ => Err(FsError::Forbidden),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use http::header::{CONTENT_TYPE, RANGE};
+use http::StatusCode;
+use percent_encoding::percent_decode_str;
+use reqwest::{Client, Method, RequestBuilder, Url};
+use webdav_handler::fs::{FsError, FsResult};
+use xmltree::Element;
+
+use crate::mime;
+use crate::quota::Quota;
+use crate::treefs::{self, Chunks, Entry};
+use crate::webdav::config::Config;
+
+const PROPFIND: &str = concat!(
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>",
+    "<D:propfind xmlns:D=\"DAV:\"><D:prop>",
+    "<D:resourcetype/><D:getcontentlength/><D:getlastmodified/>",
+    "<D:getetag/><D:getcontenttype/>",
+    "</D:prop></D:propfind>"
+);
+
+// RFC 4331 quota properties.
+const QUOTA: &str = concat!(
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>",
+    "<D:propfind xmlns:D=\"DAV:\"><D:prop>",
+    "<D:quota-used-bytes/><D:quota-available-bytes/>",
+    "</D:prop></D:propfind>"
+);
+
+#[derive(Debug, Clone)]
+pub struct WebDavClient {
+    client: Client,
+    config: Config,
+    base: Url,
+}
+
+impl WebDavClient {
+    pub fn new(config: Config) -> WebDavClient {
+        let mut url = config.url.clone();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let base = Url::parse(&url).expect("invalid url in webdav.json");
+        let client = Client::builder().build().unwrap();
+        WebDavClient {
+            client,
+            config,
+            base,
+        }
+    }
+
+    /// Path of the served collection on the server.
+    pub fn root(&self) -> String {
+        self.base.path().to_owned()
+    }
+
+    /// List the collection at `href` with a Depth: 1 PROPFIND.
+    pub async fn opendir(&self, href: &str) -> FsResult<Vec<Entry<String>>> {
+        let res = self.propfind(href, "1", PROPFIND).await?;
+        let dir = decode(href);
+        Ok(res
+            .children
+            .iter()
+            .filter_map(|c| c.as_element())
+            .filter(|e| e.name == "response")
+            .filter_map(file)
+            // the collection itself is part of the response.
+            .filter(|f| decode(&f.key).trim_end_matches('/') != dir.trim_end_matches('/'))
+            .collect())
+    }
+
+    /// Read `len` bytes from `start` of the file at `href`.
+    pub async fn read(&self, href: &str, start: u64, len: usize) -> FsResult<Bytes> {
+        if len == 0 {
+            return Ok(Bytes::new());
+        }
+        let range = format!("bytes={}-{}", start, start + len as u64 - 1);
+        let res = self
+            .request(Method::GET, href)?
+            .header(RANGE, range)
+            .send()
+            .await
+            .map_err(error)?;
+        match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Bytes::new()),
+            // the server ignored the range and sent everything.
+            StatusCode::OK => {
+                let data = res.bytes().await.map_err(error)?;
+                let start = (start as usize).min(data.len());
+                let end = (start + len).min(data.len());
+                Ok(data.slice(start..end))
+            }
+            _ => res
+                .error_for_status()
+                .map_err(error)?
+                .bytes()
+                .await
+                .map_err(error),
+        }
+    }
+
+    /// Stream the resource at `href` from `start` to its end.
+    pub async fn open(&self, href: &str, start: u64) -> FsResult<Chunks> {
+        let res = self
+            .request(Method::GET, href)?
+            .header(RANGE, format!("bytes={}-", start))
+            .send()
+            .await
+            .map_err(error)?;
+        match res.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(stream::empty().boxed()),
+            // the server ignored the range and sends everything.
+            StatusCode::OK => Ok(treefs::body(res, start)),
+            _ => Ok(treefs::body(res.error_for_status().map_err(error)?, 0)),
+        }
+    }
+
+    /// Used and total space reported for the collection, if the server
+    /// supports quotas.
+    pub async fn quota(&self) -> FsResult<Quota> {
+        let res = self.propfind(&self.root(), "0", QUOTA).await?;
+        let response = res.get_child("response").ok_or(FsError::NotFound)?;
+        let prop = |name| props(response, name).and_then(|p| p.parse::<u64>().ok());
+        let used = prop("quota-used-bytes").ok_or(FsError::NotImplemented)?;
+        Ok((used, prop("quota-available-bytes").map(|a| used + a)))
+    }
+
+    async fn propfind(&self, href: &str, depth: &str, body: &'static str) -> FsResult<Element> {
+        let method = Method::from_bytes(b"PROPFIND").unwrap();
+        let res = self
+            .request(method, href)?
+            .header("Depth", depth)
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(error)?;
+        let body = res.bytes().await.map_err(error)?;
+        Element::parse(&body[..]).map_err(|e| {
+            tracing::error!("invalid PROPFIND response for {}: {}", href, e);
+            FsError::GeneralFailure
+        })
+    }
+
+    fn request(&self, method: Method, href: &str) -> FsResult<RequestBuilder> {
+        let url = self.base.join(href).map_err(|_| FsError::NotFound)?;
+        let req = self.client.request(method, url);
+        Ok(match self.config.username.as_str() {
+            "" => req,
+            username => req.basic_auth(username, Some(&self.config.password)),
+        })
+    }
+}
+
+// a file from a <response> element of a multistatus.
+fn file(response: &Element) -> Option<Entry<String>> {
+    let href = response.get_child("href")?.get_text()?;
+    // some servers send full urls.
+    let href = match Url::parse(&href) {
+        Ok(url) => url.path().to_owned(),
+        Err(_) => href.into_owned(),
+    };
+    let name = decode(&href)
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or("")
+        .to_owned();
+    if name.is_empty() {
+        return None;
+    }
+    let is_dir = found(response)
+        .filter_map(|p| p.get_child("resourcetype"))
+        .any(|r| r.get_child("collection").is_some());
+    let mtime = props(response, "getlastmodified")
+        .and_then(|t| httpdate::parse_http_date(&t).ok())
+        .unwrap_or(UNIX_EPOCH);
+    let size = props(response, "getcontentlength")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    let etag = props(response, "getetag")
+        .map(|e| e.trim_start_matches("W/").trim_matches('"').to_owned())
+        .unwrap_or_else(|| etag(size, mtime));
+    let content_type = if is_dir {
+        "httpd/unix-directory".to_owned()
+    } else {
+        let hint = props(response, "getcontenttype");
+        mime::content_type(&name, hint.as_deref())
+            .unwrap_or(mime::DEFAULT)
+            .to_owned()
+    };
+    Some(Entry {
+        key: href,
+        name,
+        size: Some(size),
+        mtime,
+        ctime: mtime,
+        is_dir,
+        etag,
+        content_type,
+        follows: None,
+        media: None,
+    })
+}
+
+// the <prop> elements of a response that were found.
+fn found(response: &Element) -> impl Iterator<Item = &Element> {
+    response
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|e| e.name == "propstat")
+        .filter(|p| {
+            p.get_child("status")
+                .and_then(|s| s.get_text())
+                .map(|s| s.contains(" 200 "))
+                .unwrap_or(false)
+        })
+        .filter_map(|p| p.get_child("prop"))
+}
+
+// the text of a property of a response.
+fn props(response: &Element, name: &str) -> Option<String> {
+    found(response)
+        .filter_map(|p| p.get_child(name))
+        .find_map(|p| p.get_text())
+        .map(|t| t.trim().to_owned())
+}
+
+fn etag(size: u64, mtime: SystemTime) -> String {
+    let secs = mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("{:x}-{:x}", size, secs)
+}
+
+fn decode(href: &str) -> String {
+    percent_decode_str(href).decode_utf8_lossy().into_owned()
+}
+
+fn error(e: reqwest::Error) -> FsError {
+    tracing::error!("request failed! {}", e);
+    match e.status() {
+        Some(StatusCode::NOT_FOUND) => FsError::NotFound,
+        Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN) => FsError::Forbidden,
+        _ => FsError::GeneralFailure,
+    }
+}
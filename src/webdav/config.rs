@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Config {
+    /// The collection to serve, like `https://cloud.example.com/remote.php/dav/files/me/`.
+    pub url: String,
+    /// Basic auth credentials, if the server needs them.
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
@@ -0,0 +1,62 @@
+use std::fs;
+
+use bytes::Bytes;
+use futures::future::FutureExt;
+use webdav_handler::fs::FsFuture;
+
+use crate::options::Options;
+use crate::props::PropStore;
+use crate::quota::Quota;
+use crate::treefs::{Backend, Chunks, Entry, TreeFs};
+use crate::webdav::client::WebDavClient;
+use crate::webdav::config::Config;
+
+/// Fronts a collection on another WebDAV server, read only. Listings
+/// are cached in the tree, file content is streamed with ranged GETs.
+pub type WebDavFS = TreeFs<WebDavClient>;
+
+impl WebDavFS {
+    /// Create a new "WebDavFS" filesystem, configured by `webdav.json`.
+    pub fn new(options: &Options, props: PropStore) -> Box<WebDavFS> {
+        let demo_config = serde_json::ser::to_string(&Config::default()).unwrap();
+        let msg = format!(
+            "webdav.json does not exists, you should create with the content like:\r\n{}",
+            demo_config
+        );
+        let config_str = fs::read_to_string("webdav.json").expect(msg.as_str());
+        let config = serde_json::de::from_str::<Config>(config_str.as_str()).unwrap();
+        let client = WebDavClient::new(config);
+        Box::new(TreeFs::with_backend(client, options, props))
+    }
+}
+
+impl Backend for WebDavClient {
+    // resources are keyed by their href on the server.
+    type Key = String;
+
+    fn root(&self) -> String {
+        WebDavClient::root(self)
+    }
+
+    fn opendir<'a>(&'a self, href: &'a String) -> FsFuture<'a, Vec<Entry<String>>> {
+        WebDavClient::opendir(self, href).boxed()
+    }
+
+    fn read<'a>(&'a self, href: &'a String, start: u64, len: usize) -> FsFuture<'a, Bytes> {
+        WebDavClient::read(self, href, start, len).boxed()
+    }
+
+    fn open<'a>(&'a self, href: &'a String, start: u64) -> FsFuture<'a, Option<Chunks>> {
+        WebDavClient::open(self, href, start)
+            .map(|r| r.map(Some))
+            .boxed()
+    }
+
+    fn prop_prefix(&self) -> &'static str {
+        "webdav"
+    }
+
+    fn quota(&self) -> FsFuture<'_, Quota> {
+        WebDavClient::quota(self).boxed()
+    }
+}
@@ -0,0 +1,3 @@
+mod client;
+mod config;
+pub mod fs;